@@ -0,0 +1,257 @@
+use std::{fs, path::Path};
+use palette::{Gradient, LinSrgb, Srgb};
+use crate::terrain_common::TerrainError;
+
+/// the height a `ColorRamp` stop position refers to
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ColorRampHeight {
+    /// heightmap value, from 0 to 1
+    Normalized,
+    /// world space height, the heightmap value scaled
+    /// by `max_image_height`
+    Absolute,
+}
+
+/// Maps heights to vertex colors, interpolating linearly
+/// between color stops. Heights outside the stops take
+/// the color of the closest stop
+#[derive(Debug, Clone)]
+pub struct ColorRamp {
+    pub height: ColorRampHeight,
+    /// sorted by position
+    stops: Vec<(f32, Srgb)>,
+    gradient: Gradient<LinSrgb>,
+}
+
+impl Default for ColorRamp {
+    fn default() -> Self {
+        ColorRamp::terrain()
+    }
+}
+
+impl ColorRamp {
+    /// ramp from (position, sRGB color) stops, in any order.
+    ///
+    /// Panics if there are no stops or a position is not finite
+    pub fn new(mut stops: Vec<(f32, Srgb)>, height: ColorRampHeight) -> Self {
+        assert!(!stops.is_empty(), "a color ramp needs at least one stop");
+        assert!(stops.iter().all(|(position, _)| position.is_finite()),
+            "color ramp positions must be finite");
+
+        stops.sort_by(|left, right| left.0.partial_cmp(&right.0).unwrap());
+
+        let linear_stops = stops.iter()
+            .map(|(position, color)| (*position, color.into_linear()))
+            .collect();
+
+        ColorRamp {
+            height,
+            stops,
+            gradient: Gradient::with_domain(linear_stops),
+        }
+    }
+
+    /// The same ramp with its stops moved from 0..1 to `min`..`max`, e.g.
+    /// the range of the heightmap values given by `Heightmap::height_range`
+    /// to read the percentages of a color-relief file the GDAL way
+    pub fn stretched(&self, min: f32, max: f32) -> Self {
+        ColorRamp::new(
+            self.stops.iter()
+                .map(|(position, color)| (min + position * (max - min), *color))
+                .collect(),
+            self.height)
+    }
+
+    fn from_rgb_stops(stops: &[(f32, [f32; 3])]) -> Self {
+        ColorRamp::new(
+            stops.iter()
+                .map(|(position, [r, g, b])| (*position, Srgb::new(*r, *g, *b)))
+                .collect(),
+            ColorRampHeight::Normalized)
+    }
+
+    /// hypsometric tints from sea blue through green lowlands
+    /// and brown mountains to snow
+    pub fn terrain() -> Self {
+        ColorRamp::from_rgb_stops(&[
+            (0.00, [0.20, 0.20, 0.60]),
+            (0.15, [0.00, 0.60, 1.00]),
+            (0.25, [0.00, 0.80, 0.40]),
+            (0.50, [1.00, 1.00, 0.60]),
+            (0.75, [0.50, 0.36, 0.33]),
+            (1.00, [1.00, 1.00, 1.00]),
+        ])
+    }
+
+    /// perceptually uniform purple to yellow ramp
+    pub fn viridis() -> Self {
+        ColorRamp::from_rgb_stops(&[
+            (0.00, [0.267, 0.005, 0.329]),
+            (0.25, [0.229, 0.322, 0.546]),
+            (0.50, [0.128, 0.567, 0.551]),
+            (0.75, [0.369, 0.789, 0.383]),
+            (1.00, [0.993, 0.906, 0.144]),
+        ])
+    }
+
+    /// deep navy at the bottom to shallow cyan at the top
+    pub fn bathymetry() -> Self {
+        ColorRamp::from_rgb_stops(&[
+            (0.00, [0.03, 0.05, 0.20]),
+            (0.50, [0.10, 0.35, 0.60]),
+            (0.85, [0.45, 0.75, 0.85]),
+            (1.00, [0.85, 0.95, 0.95]),
+        ])
+    }
+
+    /// Parses a GDAL color-relief text file: one `height red green blue [alpha]`
+    /// entry per line, components from 0 to 255. Heights ending with `%` are
+    /// normalized, any other height is absolute; a file can't mix the two.
+    /// Heights must be finite.
+    ///
+    /// GDAL takes percentages of the raster min..max range, here they are
+    /// of the normalized heightmap values, 0 to 1. Use `stretched` with the
+    /// heightmap range to get the GDAL colors.
+    ///
+    /// Values may be separated by spaces, tabs, commas or colons, `#` starts
+    /// a comment. No-data (`nv`) entries are skipped, alpha is ignored and
+    /// named colors are not supported
+    pub fn from_color_relief(text: &str) -> Result<Self, TerrainError> {
+        let mut stops = Vec::new();
+        let mut height = None;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let invalid = || TerrainError::InvalidColorRamp { line: line_index + 1 };
+
+            let line = line.split('#').next().unwrap().trim();
+            let values: Vec<&str> = line
+                .split(|c: char| c.is_whitespace() || c == ',' || c == ':')
+                .filter(|value| !value.is_empty())
+                .collect();
+
+            if values.is_empty() || values[0] == "nv" {
+                continue;
+            }
+
+            if values.len() != 4 && values.len() != 5 {
+                return Err(invalid());
+            }
+
+            let (position, line_height) = match values[0].strip_suffix('%') {
+                Some(percent) => (percent.parse::<f32>().map(|p| p / 100.0),
+                    ColorRampHeight::Normalized),
+                None => (values[0].parse::<f32>(), ColorRampHeight::Absolute),
+            };
+            let position = position.ok()
+                .filter(|position| position.is_finite())
+                .ok_or_else(invalid)?;
+
+            if *height.get_or_insert(line_height) != line_height {
+                return Err(invalid());
+            }
+
+            let mut rgb = [0f32; 3];
+            for (component, value) in rgb.iter_mut().zip(&values[1..4]) {
+                let value = value.parse::<u8>()
+                    .map_err(|_| invalid())?;
+                *component = value as f32 / 255.0;
+            }
+
+            stops.push((position, Srgb::new(rgb[0], rgb[1], rgb[2])));
+        }
+
+        match height {
+            Some(height) => Ok(ColorRamp::new(stops, height)),
+            None => Err(TerrainError::EmptyColorRamp),
+        }
+    }
+
+    /// reads a GDAL color-relief file, see `from_color_relief`
+    pub fn open_color_relief<P: AsRef<Path>>(path: P) -> Result<Self, TerrainError> {
+        ColorRamp::from_color_relief(&fs::read_to_string(path)?)
+    }
+
+    /// sRGB color of a height measured as `self.height`
+    pub fn color_at(&self, height: f32) -> Srgb {
+        Srgb::from_linear(self.gradient.get(height))
+    }
+
+    /// sRGB vertex color of a normalized heightmap value
+    pub fn vertex_color(&self, normalized_height: f32, max_image_height: f32) -> [f32; 3] {
+        let height = match self.height {
+            ColorRampHeight::Normalized => normalized_height,
+            ColorRampHeight::Absolute => normalized_height * max_image_height,
+        };
+
+        let color = self.color_at(height);
+        [color.red, color.green, color.blue]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_color_near(left: [f32; 3], right: [f32; 3]) {
+        for i in 0..3 {
+            assert!((left[i] - right[i]).abs() < 1e-4, "{:?} != {:?}", left, right);
+        }
+    }
+
+    #[test]
+    fn test_color_ramp_stops() {
+        let ramp = ColorRamp::new(vec![
+            (1.0, Srgb::new(1.0, 1.0, 1.0)),
+            (0.0, Srgb::new(0.0, 0.0, 0.0)),
+        ], ColorRampHeight::Normalized);
+
+        assert_color_near(ramp.vertex_color(0.0, 10.0), [0.0, 0.0, 0.0]);
+        assert_color_near(ramp.vertex_color(1.0, 10.0), [1.0, 1.0, 1.0]);
+        assert_color_near(ramp.vertex_color(2.0, 10.0), [1.0, 1.0, 1.0]);
+
+        // interpolated in linear space
+        let mid_gray = Srgb::from_linear(LinSrgb::new(0.5, 0.5, 0.5)).red;
+        assert_color_near(ramp.vertex_color(0.5, 10.0), [mid_gray; 3]);
+    }
+
+    #[test]
+    fn test_color_ramp_from_color_relief() {
+        let ramp = ColorRamp::from_color_relief("
+            # elevation r g b
+            nv     0   0   0   0
+            100    0   0 255
+            0,255,0,0,128
+            50:0:255:0
+        ").unwrap();
+
+        assert_eq!(ramp.height, ColorRampHeight::Absolute);
+        assert_color_near(ramp.vertex_color(0.0, 100.0), [1.0, 0.0, 0.0]);
+        assert_color_near(ramp.vertex_color(0.5, 100.0), [0.0, 1.0, 0.0]);
+        assert_color_near(ramp.vertex_color(1.0, 100.0), [0.0, 0.0, 1.0]);
+
+        let ramp = ColorRamp::from_color_relief("0% 0 0 0\n100% 255 255 255").unwrap();
+        assert_eq!(ramp.height, ColorRampHeight::Normalized);
+        assert_color_near(ramp.vertex_color(1.0, 100.0), [1.0, 1.0, 1.0]);
+
+        // percentages of the heightmap range, as GDAL reads them
+        let ramp = ramp.stretched(0.2, 0.6);
+        assert_color_near(ramp.vertex_color(0.2, 100.0), [0.0, 0.0, 0.0]);
+        assert_color_near(ramp.vertex_color(0.6, 100.0), [1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_invalid_color_relief() {
+        let invalid_line = |text| match ColorRamp::from_color_relief(text) {
+            Err(TerrainError::InvalidColorRamp { line }) => line,
+            _ => panic!("expected an invalid color ramp"),
+        };
+
+        assert_eq!(invalid_line("0 0 0 0\n10% 255 255 255"), 2);
+        assert_eq!(invalid_line("0 0 0 300"), 1);
+        assert_eq!(invalid_line("0 white"), 1);
+        assert_eq!(invalid_line("0 0 0 0\nnan 255 255 255"), 2);
+        assert_eq!(invalid_line("inf% 0 0 0"), 1);
+        assert!(matches!(ColorRamp::from_color_relief("# nothing\n"),
+            Err(TerrainError::EmptyColorRamp)));
+    }
+}
@@ -0,0 +1,618 @@
+use crate::terrain_common::TerrainError;
+use bevy::reflect::TypeUuid;
+use image::{DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Luma};
+use std::{io::Cursor, path::Path, sync::Arc, vec::Vec};
+use tiff::decoder::DecodingResult;
+
+pub type HeightMapU8 = ImageBuffer<Luma<u8>, Vec::<u8>>;
+pub type HeightMapU16 = ImageBuffer<Luma<u16>, Vec::<u16>>;
+pub type HeightMapF32 = ImageBuffer<Luma<f32>, Vec::<f32>>;
+
+/// anything that can be sampled as a grid of heights, 
+/// e.g. images, plain height vectors or procedural functions
+pub trait HeightSource {
+    /// width and height of the grid
+    fn size(&self) -> (u32, u32);
+
+    /// height of the grid point, `x` and `y` are always within `size`
+    fn height_at(&self, x: u32, y: u32) -> f32;
+
+    fn width(&self) -> u32 {
+        self.size().0
+    }
+
+    fn height(&self) -> u32 {
+        self.size().1
+    }
+}
+
+impl<H: HeightSource + ?Sized> HeightSource for &H {
+    fn size(&self) -> (u32, u32) {
+        (**self).size()
+    }
+
+    fn height_at(&self, x: u32, y: u32) -> f32 {
+        (**self).height_at(x, y)
+    }
+}
+
+impl HeightSource for HeightMapU8 {
+    fn size(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+
+    fn height_at(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y).0[0] as f32 / std::u8::MAX as f32
+    }
+}
+
+impl HeightSource for HeightMapU16 {
+    fn size(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+
+    fn height_at(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y).0[0] as f32 / std::u16::MAX as f32
+    }
+}
+
+impl HeightSource for HeightMapF32 {
+    fn size(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+
+    fn height_at(&self, x: u32, y: u32) -> f32 {
+        self.get_pixel(x, y).0[0]
+    }
+}
+
+/// heights computed on the fly by a closure, 
+/// e.g. procedural noise or simulation output
+pub struct FnHeightSource<F> {
+    width: u32,
+    height: u32,
+    height_fn: F,
+}
+
+impl<F> FnHeightSource<F> where F: Fn(u32, u32) -> f32 {
+    pub fn new(width: u32, height: u32, height_fn: F) -> Self {
+        FnHeightSource {
+            width,
+            height,
+            height_fn
+        }
+    }
+}
+
+impl<F> HeightSource for FnHeightSource<F> where F: Fn(u32, u32) -> f32 {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn height_at(&self, x: u32, y: u32) -> f32 {
+        (self.height_fn)(x, y)
+    }
+}
+
+/// sine pattern shared by the meshing tests, with errors at every level
+#[cfg(test)]
+pub(crate) fn sine_height_source(width: u32, height: u32) -> FnHeightSource<impl Fn(u32, u32) -> f32> {
+    FnHeightSource::new(width, height, 
+        |x, y| ((x as f32 * 0.4).sin() * (y as f32 * 0.3).cos()).abs())
+}
+
+/// rec. 709 luma coefficients, the same used by the image crate
+const LUMA_COEFFICIENTS: [f32; 3] = [0.2126, 0.7152, 0.0722];
+
+/// which part of a color pixel holds the height
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HeightChannel {
+    Luminance,
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Default for HeightChannel {
+    fn default() -> Self {
+        HeightChannel::Luminance
+    }
+}
+
+impl HeightChannel {
+
+    /// channel of a lowercase name such as `red`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "luminance" => Some(HeightChannel::Luminance),
+            "red" => Some(HeightChannel::Red),
+            "green" => Some(HeightChannel::Green),
+            "blue" => Some(HeightChannel::Blue),
+            "alpha" => Some(HeightChannel::Alpha),
+            _ => None,
+        }
+    }
+
+    /// sample the height of a normalized rgba pixel
+    pub fn sample(&self, rgba: [f32; 4]) -> f32 {
+        match self {
+            HeightChannel::Luminance =>
+                rgba[0] * LUMA_COEFFICIENTS[0] +
+                rgba[1] * LUMA_COEFFICIENTS[1] +
+                rgba[2] * LUMA_COEFFICIENTS[2],
+            HeightChannel::Red => rgba[0],
+            HeightChannel::Green => rgba[1],
+            HeightChannel::Blue => rgba[2],
+            HeightChannel::Alpha => rgba[3],
+        }
+    }
+}
+
+/// how heights are stored in the heightmap pixels
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HeightEncoding {
+    /// a single channel selected by `HeightChannel`, normalized in [0, 1]
+    Normalized,
+    /// Mapbox Terrain-RGB tiles, elevation in meters
+    /// `-10000 + (R * 65536 + G * 256 + B) * 0.1`
+    MapboxTerrainRgb,
+    /// Terrarium tiles, elevation in meters
+    /// `R * 256 + G + B / 256 - 32768`
+    Terrarium,
+}
+
+impl Default for HeightEncoding {
+    fn default() -> Self {
+        HeightEncoding::Normalized
+    }
+}
+
+impl HeightEncoding {
+
+    /// encoding of a lowercase name, `normalized`, `mapbox` or `terrarium`
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "normalized" => Some(HeightEncoding::Normalized),
+            "mapbox" => Some(HeightEncoding::MapboxTerrainRgb),
+            "terrarium" => Some(HeightEncoding::Terrarium),
+            _ => None,
+        }
+    }
+
+    /// decode the elevation of an rgb encoded pixel
+    pub fn decode_rgb(&self, rgb: [u8; 3]) -> f32 {
+        let (r, g, b) = (rgb[0] as f64, rgb[1] as f64, rgb[2] as f64);
+        let elevation = match self {
+            HeightEncoding::Normalized => 
+                HeightChannel::Luminance.sample(
+                    [r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0, 1.0]) as f64,
+            HeightEncoding::MapboxTerrainRgb => 
+                -10000.0 + (r * 65536.0 + g * 256.0 + b) * 0.1,
+            HeightEncoding::Terrarium => 
+                r * 256.0 + g + b / 256.0 - 32768.0,
+        };
+        elevation as f32
+    }
+}
+
+/// A grid of heights, one per heightmap pixel.
+///
+/// Heights of integer images are normalized in [0, 1],
+/// heights of floating point images are kept as they are and
+/// rgb encoded elevation tiles are decoded in meters, see
+/// `TerrainImageLoadOptions::max_image_height` to mesh them in their units.
+///
+/// OpenEXR is out of scope, the `image` crate version used here cannot
+/// decode it: convert EXR heightmaps to 32-bit float TIFFs instead.
+///
+/// Clones share the heights, cloning the asset is cheap
+#[derive(Debug, Clone, PartialEq, TypeUuid)]
+#[uuid = "9e1e0c3c-4b1f-4a55-a7a3-1c4d0e6f2b8d"]
+pub struct Heightmap {
+    width: u32,
+    height: u32,
+    heights: Arc<[f32]>,
+}
+
+impl Heightmap {
+
+    /// `heights` is stored row by row
+    pub fn new(width: u32, height: u32, heights: Vec::<f32>) -> Self {
+        assert_eq!(heights.len(), (width * height) as usize);
+        Heightmap {
+            width,
+            height,
+            heights: heights.into(),
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// lowest and highest height, `None` when empty
+    pub fn height_range(&self) -> Option<(f32, f32)> {
+        let first = *self.heights.first()?;
+        Some(self.heights.iter().fold((first, first), 
+            |(min, max), height| (min.min(*height), max.max(*height))))
+    }
+
+    pub fn get_height(&self, x: u32, y: u32) -> f32 {
+        self.heights[(y * self.width + x) as usize]
+    }
+
+    pub fn from_luma8(image: &HeightMapU8) -> Self {
+        let heights = image.pixels()
+            .map(|pixel| pixel.0[0] as f32 / std::u8::MAX as f32)
+            .collect();
+        Heightmap::new(image.width(), image.height(), heights)
+    }
+
+    pub fn from_luma16(image: &HeightMapU16) -> Self {
+        let heights = image.pixels()
+            .map(|pixel| pixel.0[0] as f32 / std::u16::MAX as f32)
+            .collect();
+        Heightmap::new(image.width(), image.height(), heights)
+    }
+
+    /// floating point heights, e.g. decoded by `from_tiff_bytes`,
+    /// kept in their own units instead of normalized
+    pub fn from_luma32f(image: &HeightMapF32) -> Self {
+        let heights = image.pixels()
+            .map(|pixel| pixel.0[0])
+            .collect();
+        Heightmap::new(image.width(), image.height(), heights)
+    }
+
+    /// sample any height source into a plain grid
+    pub fn from_height_source<H: HeightSource + ?Sized>(source: &H) -> Self {
+        let (width, height) = source.size();
+        let heights = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| source.height_at(x, y))
+            .collect();
+        Heightmap::new(width, height, heights)
+    }
+
+    /// build a heightmap from any gray or color image.
+    /// `channel` selects the height of color images, gray images
+    /// always use their gray value unless the alpha channel is requested
+    pub fn from_image(image: &DynamicImage, channel: HeightChannel) -> Result<Self, TerrainError> {
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(TerrainError::EmptyImage);
+        }
+
+        if channel == HeightChannel::Alpha && !image.color().has_alpha() {
+            return Err(TerrainError::UnsupportedPixelFormat(image.color()));
+        }
+
+        let heightmap = match image {
+            DynamicImage::ImageLuma8(gray) if channel != HeightChannel::Alpha =>
+                Heightmap::from_luma8(gray),
+            DynamicImage::ImageLuma16(gray) if channel != HeightChannel::Alpha =>
+                Heightmap::from_luma16(gray),
+            _ => {
+                let color = image.color();
+                let bytes_per_channel = 
+                    color.bytes_per_pixel() / color.channel_count();
+
+                let heights = if bytes_per_channel == 1 {
+                    image.to_rgba8().pixels()
+                        .map(|pixel| channel.sample(normalize_rgba(
+                            pixel.0, std::u8::MAX)))
+                        .collect()
+                } else {
+                    image.to_rgba16().pixels()
+                        .map(|pixel| channel.sample(normalize_rgba(
+                            pixel.0, std::u16::MAX)))
+                        .collect()
+                };
+
+                Heightmap::new(width, height, heights)
+            }
+        };
+
+        Ok(heightmap)
+    }
+
+    /// build a heightmap from an 8 bit rgb(a) image storing
+    /// elevations with the given encoding
+    pub fn from_encoded_image(image: &DynamicImage, encoding: HeightEncoding) -> Result<Self, TerrainError> {
+        let (width, height) = image.dimensions();
+        if width == 0 || height == 0 {
+            return Err(TerrainError::EmptyImage);
+        }
+
+        let color = image.color();
+        let bytes_per_channel = color.bytes_per_pixel() / color.channel_count();
+        let color_channels = if color.has_alpha() { 
+            color.channel_count() - 1 
+        } else { 
+            color.channel_count() 
+        };
+
+        if bytes_per_channel != 1 || color_channels != 3 {
+            return Err(TerrainError::UnsupportedPixelFormat(color));
+        }
+
+        let heights = image.to_rgb8().pixels()
+            .map(|pixel| encoding.decode_rgb(pixel.0))
+            .collect();
+
+        Ok(Heightmap::new(width, height, heights))
+    }
+
+    pub fn decode(image: &DynamicImage, 
+        encoding: HeightEncoding, channel: HeightChannel) -> Result<Self, TerrainError> {
+        match encoding {
+            HeightEncoding::Normalized => Heightmap::from_image(image, channel),
+            _ => Heightmap::from_encoded_image(image, encoding),
+        }
+    }
+
+    /// read a headerless square heightmap of little endian u16 samples
+    pub fn from_r16_bytes(bytes: &[u8]) -> Result<Self, TerrainError> {
+        if bytes.is_empty() {
+            return Err(TerrainError::EmptyImage);
+        }
+
+        let samples = (bytes.len() / 2) as u32;
+        let side = (samples as f64).sqrt() as u32;
+
+        if bytes.len() % 2 != 0 || side * side != samples {
+            return Err(TerrainError::InvalidDimensions {
+                width: samples,
+                height: 1
+            });
+        }
+
+        let heights = bytes.chunks_exact(2)
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]) as f32
+                / std::u16::MAX as f32)
+            .collect();
+
+        Ok(Heightmap::new(side, side, heights))
+    }
+
+    /// Decode a TIFF file. image 0.23 has no floating point images, so
+    /// the 32 and 64 bit float gray TIFFs of elevation models are read
+    /// here with their heights kept as they are, other TIFFs are decoded
+    /// like any image
+    pub fn from_tiff_bytes(bytes: &[u8], 
+        encoding: HeightEncoding, channel: HeightChannel) -> Result<Self, TerrainError> {
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(bytes))?;
+        let (width, height) = decoder.dimensions()?;
+
+        let heights = match decoder.colortype()? {
+            tiff::ColorType::Gray(32) | tiff::ColorType::Gray(64) => match decoder.read_image()? {
+                DecodingResult::F32(heights) => Some(heights),
+                DecodingResult::F64(heights) => 
+                    Some(heights.into_iter().map(|height| height as f32).collect()),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        match heights {
+            Some(_) if width == 0 || height == 0 => Err(TerrainError::EmptyImage),
+            Some(heights) => Ok(Heightmap::new(width, height, heights)),
+            None => {
+                let image = image::load_from_memory_with_format(bytes, ImageFormat::Tiff)?;
+                Heightmap::decode(&image, encoding, channel)
+            }
+        }
+    }
+
+    /// decode an image file of any format, see `from_tiff_bytes` for TIFFs
+    pub fn from_image_bytes(bytes: &[u8], 
+        encoding: HeightEncoding, channel: HeightChannel) -> Result<Self, TerrainError> {
+        if image::guess_format(bytes)? == ImageFormat::Tiff {
+            Heightmap::from_tiff_bytes(bytes, encoding, channel)
+        } else {
+            Heightmap::decode(&image::load_from_memory(bytes)?, encoding, channel)
+        }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P, 
+        encoding: HeightEncoding, channel: HeightChannel) -> Result<Self, TerrainError> {
+        if ImageFormat::from_path(&path).ok() == Some(ImageFormat::Tiff) {
+            return Heightmap::from_tiff_bytes(&std::fs::read(path)?, encoding, channel);
+        }
+        let image = image::open(path)?;
+        Heightmap::decode(&image, encoding, channel)
+    }
+}
+
+impl HeightSource for Heightmap {
+    fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn height_at(&self, x: u32, y: u32) -> f32 {
+        self.get_height(x, y)
+    }
+}
+
+/// How to decode a heightmap image, read from a small `key = value`
+/// text file next to it:
+///
+/// ```text
+/// # lines starting with # are comments
+/// image = terrain.png
+/// encoding = terrarium
+/// channel = red
+/// ```
+///
+/// `image` is relative to the description file, `encoding` and
+/// `channel` are optional
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightmapDescription {
+    pub image: String,
+    pub encoding: HeightEncoding,
+    pub channel: HeightChannel,
+}
+
+impl HeightmapDescription {
+    /// parse a description, `encoding` and `channel` are used
+    /// when the text does not set them
+    pub fn parse(text: &str, 
+        encoding: HeightEncoding, channel: HeightChannel) -> Result<Self, TerrainError> {
+        let mut image = None;
+        let mut description_encoding = encoding;
+        let mut description_channel = channel;
+
+        for (line_index, line) in text.lines().enumerate() {
+            let invalid = || TerrainError::InvalidHeightmapDescription { line: line_index + 1 };
+
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut key_value = line.splitn(2, '=').map(str::trim);
+            let (key, value) = match (key_value.next(), key_value.next()) {
+                (Some(key), Some(value)) if !value.is_empty() => (key, value),
+                _ => return Err(invalid()),
+            };
+
+            match key {
+                "image" => image = Some(value.to_string()),
+                "encoding" => description_encoding = 
+                    HeightEncoding::from_name(value).ok_or_else(invalid)?,
+                "channel" => description_channel = 
+                    HeightChannel::from_name(value).ok_or_else(invalid)?,
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(HeightmapDescription {
+            image: image.ok_or(TerrainError::InvalidHeightmapDescription { line: 0 })?,
+            encoding: description_encoding,
+            channel: description_channel,
+        })
+    }
+}
+
+fn normalize_rgba<T: Into<f32> + Copy>(rgba: [T; 4], max_value: T) -> [f32; 4] {
+    let max_value = max_value.into();
+    [
+        rgba[0].into() / max_value,
+        rgba[1].into() / max_value,
+        rgba[2].into() / max_value,
+        rgba[3].into() / max_value,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_heightmap_from_image() {
+        let gray = HeightMapU8::from_vec(2, 1, vec![0u8, 255u8]).unwrap();
+        let heightmap = Heightmap::from_image(
+            &DynamicImage::ImageLuma8(gray), HeightChannel::Luminance).unwrap();
+        assert_eq!(heightmap.heights(), &[0.0, 1.0]);
+
+        let mut color = RgbImage::new(1, 1);
+        color.put_pixel(0, 0, Rgb([255, 0, 51]));
+        let color = DynamicImage::ImageRgb8(color);
+
+        let heightmap = Heightmap::from_image(&color, HeightChannel::Blue).unwrap();
+        assert_eq!(heightmap.heights(), &[0.2]);
+
+        let heightmap = Heightmap::from_image(&color, HeightChannel::Luminance).unwrap();
+        assert!((heightmap.get_height(0, 0) - (0.2126 + 0.0722 * 0.2)).abs() < 1e-6);
+
+        assert!(matches!(Heightmap::from_image(&color, HeightChannel::Alpha),
+            Err(TerrainError::UnsupportedPixelFormat(_))));
+    }
+
+    #[test]
+    fn test_heightmap_from_encoded_image() {
+        let mut tile = RgbImage::new(2, 1);
+        tile.put_pixel(0, 0, Rgb([1, 134, 160]));
+        tile.put_pixel(1, 0, Rgb([1, 134, 170]));
+        let tile = DynamicImage::ImageRgb8(tile);
+
+        let heightmap = Heightmap::from_encoded_image(
+            &tile, HeightEncoding::MapboxTerrainRgb).unwrap();
+        assert_eq!(heightmap.heights(), &[0.0, 1.0]);
+
+        let mut tile = RgbImage::new(2, 1);
+        tile.put_pixel(0, 0, Rgb([128, 0, 0]));
+        tile.put_pixel(1, 0, Rgb([128, 100, 128]));
+        let tile = DynamicImage::ImageRgb8(tile);
+
+        let heightmap = Heightmap::from_encoded_image(
+            &tile, HeightEncoding::Terrarium).unwrap();
+        assert_eq!(heightmap.heights(), &[0.0, 100.5]);
+
+        let gray = DynamicImage::ImageLuma8(HeightMapU8::new(1, 1));
+        assert!(matches!(Heightmap::from_encoded_image(&gray, HeightEncoding::Terrarium),
+            Err(TerrainError::UnsupportedPixelFormat(_))));
+    }
+
+    #[test]
+    fn test_heightmap_description() {
+        let description = HeightmapDescription::parse(
+            "# tiles\nimage = tiles/0.png\nencoding = terrarium # rgb\n",
+            HeightEncoding::Normalized, HeightChannel::Red).unwrap();
+        assert_eq!(description, HeightmapDescription {
+            image: "tiles/0.png".to_string(),
+            encoding: HeightEncoding::Terrarium,
+            channel: HeightChannel::Red,
+        });
+
+        assert!(matches!(HeightmapDescription::parse("image = a.png\nchannel = cyan",
+                HeightEncoding::Normalized, HeightChannel::Luminance),
+            Err(TerrainError::InvalidHeightmapDescription { line: 2 })));
+        assert!(matches!(HeightmapDescription::parse("encoding = mapbox",
+                HeightEncoding::Normalized, HeightChannel::Luminance),
+            Err(TerrainError::InvalidHeightmapDescription { line: 0 })));
+    }
+
+    #[test]
+    fn test_heightmap_from_float_tiff() {
+        let mut bytes = Cursor::new(Vec::new());
+        tiff::encoder::TiffEncoder::new(&mut bytes).unwrap()
+            .write_image::<tiff::encoder::colortype::Gray32Float>(2, 1, &[-12.5, 1834.25])
+            .unwrap();
+        let bytes = bytes.into_inner();
+
+        let heightmap = Heightmap::from_image_bytes(
+            &bytes, HeightEncoding::Normalized, HeightChannel::Luminance).unwrap();
+        assert_eq!(heightmap.heights(), &[-12.5, 1834.25]);
+        assert_eq!(heightmap.height_range(), Some((-12.5, 1834.25)));
+
+        let mut gray = Cursor::new(Vec::new());
+        tiff::encoder::TiffEncoder::new(&mut gray).unwrap()
+            .write_image::<tiff::encoder::colortype::Gray8>(2, 1, &[0, 255])
+            .unwrap();
+        let heightmap = Heightmap::from_tiff_bytes(
+            &gray.into_inner(), HeightEncoding::Normalized, HeightChannel::Luminance).unwrap();
+        assert_eq!(heightmap.heights(), &[0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_heightmap_from_r16_bytes() {
+        let heightmap = Heightmap::from_r16_bytes(
+            &[0, 0, 255, 255, 0, 128, 0, 0]).unwrap();
+        assert_eq!(heightmap.size(), (2, 2));
+        assert_eq!(heightmap.get_height(1, 0), 1.0);
+
+        assert!(matches!(Heightmap::from_r16_bytes(&[0, 0, 0, 0]),
+            Err(TerrainError::InvalidDimensions { .. })));
+    }
+}
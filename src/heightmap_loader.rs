@@ -0,0 +1,153 @@
+use crate::{
+    heightmap::{HeightChannel, HeightEncoding, Heightmap, HeightmapDescription},
+    terrain_common::TerrainError,
+};
+use bevy::{
+    asset::{AssetLoader, AssetPath, AssetServerSettings, FileAssetIo, LoadContext, LoadedAsset},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// seconds between two checks of the images of the descriptions
+const HEIGHTMAP_IMAGE_CHECK_PERIOD: f64 = 1.0;
+
+/// Loads `Heightmap` assets from raw .r16 files, from TIFFs and from
+/// .heightmap descriptions of how to decode an image, see
+/// `HeightmapDescription`.
+///
+/// Other image formats are not loaded directly so that their extensions
+/// stay with the texture loaders. The asset server only watches the
+/// description, `heightmap_image_watcher_system` reloads the heightmap
+/// when its image changes
+#[derive(Default)]
+pub struct HeightmapLoader {
+    /// channel of the descriptions not setting one and of the TIFFs
+    pub height_channel: HeightChannel,
+    /// encoding of the descriptions not setting one and of the TIFFs
+    pub height_encoding: HeightEncoding,
+    /// where the images of the loaded descriptions are recorded
+    pub images: HeightmapImages,
+}
+
+impl AssetLoader for HeightmapLoader {
+    fn load<'a>(
+        &'a self,
+        bytes: &'a [u8],
+        load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<(), anyhow::Error>> {
+        Box::pin(async move {
+            let extension = load_context.path().extension()
+                .and_then(|extension| extension.to_str())
+                .map(str::to_ascii_lowercase);
+
+            let heightmap = match extension.as_deref() {
+                Some("r16") => Heightmap::from_r16_bytes(bytes)?,
+                Some("tif") | Some("tiff") =>
+                    Heightmap::from_tiff_bytes(bytes, self.height_encoding, self.height_channel)?,
+                _ => {
+                    let description = HeightmapDescription::parse(
+                        std::str::from_utf8(bytes)?, self.height_encoding, self.height_channel)?;
+                    let image_path = load_context.path().parent()
+                        .map_or_else(|| description.image.clone().into(),
+                            |directory| directory.join(&description.image));
+                    let image_bytes = load_context.read_asset_bytes(&image_path).await?;
+                    let heightmap = Heightmap::from_image_bytes(
+                        &image_bytes, description.encoding, description.channel)?;
+
+                    self.images.insert(load_context.path().to_path_buf(),
+                        image_path, description.encoding, description.channel);
+                    heightmap
+                }
+            };
+
+            load_context.set_default_asset(LoadedAsset::new(heightmap));
+            Ok(())
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["heightmap", "r16", "tif", "tiff"]
+    }
+}
+
+/// the image a loaded description decodes, with its last modification
+struct DescribedImage {
+    path: PathBuf,
+    encoding: HeightEncoding,
+    channel: HeightChannel,
+    modified: Option<SystemTime>,
+}
+
+/// The images read by the loaded .heightmap descriptions, by description
+/// path. Shared by the `HeightmapLoader` recording them and
+/// `heightmap_image_watcher_system` checking them
+#[derive(Default, Clone)]
+pub struct HeightmapImages {
+    descriptions: Arc<Mutex<HashMap<PathBuf, DescribedImage>>>,
+}
+
+impl HeightmapImages {
+    fn insert(&self, description: PathBuf,
+        image: PathBuf, encoding: HeightEncoding, channel: HeightChannel) {
+        self.descriptions.lock().unwrap().insert(description, DescribedImage {
+            path: image,
+            encoding,
+            channel,
+            // set by the first check, the image was just read
+            modified: None,
+        });
+    }
+}
+
+/// Reloads the heightmaps of the .heightmap descriptions whose image
+/// changed on disk since the last check, like the asset server does
+/// for the files it watches. Descriptions no longer loaded are skipped
+pub fn heightmap_image_watcher_system(
+    time: Res<Time>,
+    mut last_check: Local<f64>,
+    asset_server_settings: Res<AssetServerSettings>,
+    heightmap_images: Res<HeightmapImages>,
+    mut heightmaps: ResMut<Assets<Heightmap>>,
+) {
+    let now = time.seconds_since_startup();
+    if now - *last_check < HEIGHTMAP_IMAGE_CHECK_PERIOD {
+        return;
+    }
+    *last_check = now;
+
+    let asset_folder = FileAssetIo::get_root_path().join(&asset_server_settings.asset_folder);
+    let mut descriptions = heightmap_images.descriptions.lock().unwrap();
+
+    for (description_path, image) in descriptions.iter_mut() {
+        let image_path = asset_folder.join(&image.path);
+        let modified = match fs::metadata(&image_path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            // removed, or being replaced
+            Err(_) => continue,
+        };
+        if image.modified.replace(modified).map_or(true, |previous| previous == modified) {
+            continue;
+        }
+
+        let description_id = AssetPath::from(description_path.as_path());
+        if heightmaps.get(description_id.clone()).is_none() {
+            continue;
+        }
+
+        let heightmap = fs::read(&image_path)
+            .map_err(TerrainError::from)
+            .and_then(|bytes| Heightmap::from_image_bytes(&bytes, image.encoding, image.channel));
+        match heightmap {
+            // sends the modified event re-meshing the terrains
+            Ok(heightmap) => heightmaps.set_untracked(description_id, heightmap),
+            Err(err) => error!("cannot reload heightmap image {}: {}", image_path.display(), err),
+        }
+    }
+}
@@ -0,0 +1,140 @@
+use image::{GrayImage, Luma};
+use crate::heightmap::HeightSource;
+
+/// Hillshade lighting, with the same parameters and defaults as
+/// `gdaldem hillshade`. The first heightmap row is north
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HillshadeOptions {
+    /// direction of the light, in degrees clockwise from north
+    pub azimuth: f32,
+    /// elevation of the light above the horizon, in degrees
+    pub altitude: f32,
+    /// vertical exaggeration
+    pub z_factor: f32,
+    /// combine lights from 225, 270, 315 and 360 degrees weighted by the
+    /// slope aspect, ignoring `azimuth`
+    pub multidirectional: bool,
+}
+
+impl Default for HillshadeOptions {
+    fn default() -> Self {
+        HillshadeOptions {
+            azimuth: 315.0,
+            altitude: 45.0,
+            z_factor: 1.0,
+            multidirectional: false,
+        }
+    }
+}
+
+/// gradient of the height towards east and north, with Horn's method
+fn horn_gradient<H: HeightSource + ?Sized>(heightmap: &H, x: u32, y: u32) -> (f32, f32) {
+    let sample = |dx: i64, dy: i64| {
+        let sx = (x as i64 + dx).max(0).min(heightmap.width() as i64 - 1);
+        let sy = (y as i64 + dy).max(0).min(heightmap.height() as i64 - 1);
+        heightmap.height_at(sx as u32, sy as u32)
+    };
+
+    let west = sample(-1, -1) + 2.0 * sample(-1, 0) + sample(-1, 1);
+    let east = sample(1, -1) + 2.0 * sample(1, 0) + sample(1, 1);
+    let north = sample(-1, -1) + 2.0 * sample(0, -1) + sample(1, -1);
+    let south = sample(-1, 1) + 2.0 * sample(0, 1) + sample(1, 1);
+
+    ((east - west) / 8.0, (north - south) / 8.0)
+}
+
+/// Illumination of heightmap pixel (x, y) from 0 in shadow to 1 facing
+/// the light. Border pixels reuse their inner neighbours, like
+/// `gdaldem hillshade -compute_edges`
+pub fn hillshade_at<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    x: u32,
+    y: u32,
+    options: &HillshadeOptions,
+    pixel_side_length: f32,
+    max_image_height: f32) -> f32 {
+
+    let (dz_east, dz_north) = horn_gradient(heightmap, x, y);
+    let scale = options.z_factor * max_image_height / pixel_side_length;
+    let (dz_east, dz_north) = (dz_east * scale, dz_north * scale);
+
+    let slope = (dz_east * dz_east + dz_north * dz_north).sqrt().atan();
+    // compass direction the slope faces, downhill
+    let aspect = (-dz_east).atan2(-dz_north);
+    let zenith = (90.0 - options.altitude).to_radians();
+
+    let illumination = |azimuth: f32| {
+        zenith.cos() * slope.cos() +
+            zenith.sin() * slope.sin() * (azimuth.to_radians() - aspect).cos()
+    };
+
+    let shade = if options.multidirectional {
+        // the weights of four lights 45 degrees apart always sum to 2
+        [225f32, 270.0, 315.0, 360.0].iter()
+            .map(|azimuth| (aspect - azimuth.to_radians()).sin().powi(2) * illumination(*azimuth))
+            .sum::<f32>() / 2.0
+    } else {
+        illumination(options.azimuth)
+    };
+
+    shade.max(0.0).min(1.0)
+}
+
+/// The whole heightmap hillshade as an 8-bit image, encoded as
+/// `gdaldem hillshade` does: from 1 in shadow to 255, 0 being no-data
+pub fn hillshade_image<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    options: &HillshadeOptions,
+    pixel_side_length: f32,
+    max_image_height: f32) -> GrayImage {
+
+    GrayImage::from_fn(heightmap.width(), heightmap.height(), |x, y| {
+        let shade = hillshade_at(
+            heightmap, x, y, options, pixel_side_length, max_image_height);
+        Luma([if shade > 0.0 { (1.0 + 254.0 * shade).round() as u8 } else { 1 }])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::FnHeightSource;
+
+    #[test]
+    fn test_flat_hillshade() {
+        let flat = FnHeightSource::new(3, 3, |_, _| 0.5);
+        let image = hillshade_image(&flat, &HillshadeOptions::default(), 1.0, 1.0);
+
+        // sin(45 degrees)
+        assert!(image.pixels().all(|pixel| pixel[0] == 181));
+
+        let multidirectional = HillshadeOptions {
+            multidirectional: true,
+            ..Default::default()
+        };
+        let shade = hillshade_at(&flat, 1, 1, &multidirectional, 1.0, 1.0);
+        assert!((shade - 0.5f32.sqrt()).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_slope_facing_the_light() {
+        // rising towards east, so facing west
+        let slope = FnHeightSource::new(3, 3, |x, _| x as f32);
+        let light_from = |azimuth| hillshade_at(&slope, 1, 1, &HillshadeOptions {
+            azimuth,
+            ..Default::default()
+        }, 1.0, 1.0);
+
+        assert!((light_from(270.0) - 1.0).abs() < 1e-5);
+        assert!(light_from(90.0) < 1e-5);
+        assert!(light_from(0.0) < light_from(270.0));
+
+        // a steeper terrain gets darker away from the light
+        let steep = hillshade_at(&slope, 1, 1, &HillshadeOptions {
+            azimuth: 0.0,
+            z_factor: 4.0,
+            ..Default::default()
+        }, 1.0, 1.0);
+        assert!(steep < light_from(0.0));
+    }
+}
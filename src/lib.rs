@@ -1,6 +1,18 @@
 pub mod terrain;
 pub mod rtin;
 pub mod terrain_rtin;
+pub mod terrain_tiles;
+pub mod terrain_roam;
 pub mod terrain_material;
 pub mod gizmo;
-pub mod terrain_common;
\ No newline at end of file
+pub mod terrain_common;
+pub mod heightmap;
+pub mod normals;
+pub mod color_ramp;
+pub mod vertex_colorizer;
+pub mod hillshade;
+pub mod skirt;
+pub mod heightmap_loader;
+pub mod terrain_plugin;
+#[cfg(feature = "debug")]
+pub mod ui;
\ No newline at end of file
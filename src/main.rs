@@ -1,88 +1,75 @@
-mod ui;
-
-use bevy_terrain::{terrain_common::{
-    Terrain, TerrainImageLoadOptions, TerrainMeshResource}, terrain_rtin::{RtinParams, rtin_load_terrain}};
-use bevy_terrain::{gizmo::add_axis_gizmo, terrain::{terrain_example}, terrain_material::TerrainMaterial};
+use bevy_terrain::terrain_common::{Terrain, TerrainImageLoadOptions};
+use bevy_terrain::{gizmo::add_axis_gizmo, terrain::{terrain_example}};
+use bevy_terrain::{terrain_material::{TerrainMaterial, TerrainPipeline}, terrain_plugin::TerrainPlugin, ui::TerrainDebugPlugin};
 use bevy::prelude::*;
 use bevy_fly_camera::{FlyCamera, FlyCameraPlugin};
 use bevy_render::{
     mesh::{Mesh},
 };
-use bevy_terrain::terrain_material::add_terrain_material;
-use ui::{ButtonMaterials, button_system, setup_ui, show_ui_system, update_terrain_system};
 
 use bevy::{
     render::{
-        pipeline::{PipelineDescriptor, RenderPipeline},
-        render_graph::{RenderGraph},
+        pipeline::{RenderPipeline},
     },
 };
 
 fn main() {
 
-    terrain_example();
+    if let Err(err) = terrain_example() {
+        eprintln!("cannot load the example terrain: {}", err);
+    }
 
     App::build()
         .add_resource(Msaa { samples: 4 })
         .add_plugins(DefaultPlugins)
         .add_plugin(FlyCameraPlugin)
-        .add_asset::<TerrainMaterial>()
-        .init_resource::<ButtonMaterials>()
-        .init_resource::<TerrainMeshResource>()
-        .init_resource::<RtinParams>()
+        .add_plugin(TerrainPlugin {
+            watch_heightmap_images: true,
+            ..Default::default()
+        })
+        .add_plugin(TerrainDebugPlugin)
         .add_startup_system(setup.system())
-        .add_system(button_system.system())
-        .add_system(update_terrain_system.system())
-        .add_system(show_ui_system.system())
         .run();
 }
 
 
 fn setup(
     commands: &mut Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
+    meshes: ResMut<Assets<Mesh>>,
     materials: ResMut<Assets<StandardMaterial>>,
     asset_server: Res<AssetServer>,
-    button_materials: Res<ButtonMaterials>,
-    pipelines: ResMut<Assets<PipelineDescriptor>>,
-    shaders: ResMut<Assets<Shader>>,
-    render_graph: ResMut<RenderGraph>,
-    mut rtin_params: ResMut<RtinParams>,
-    mut terrain_mesh_res: ResMut<TerrainMeshResource>,
-    color_materials: ResMut<Assets<ColorMaterial>>,
+    terrain_pipeline: Res<TerrainPipeline>,
+    mut terrain_materials: ResMut<Assets<TerrainMaterial>>,
 ) {
 
-    let image_filename = "terrain.png";
-
-    rtin_params.error_threshold = 0.2;
-    rtin_params.load_options = TerrainImageLoadOptions {
-        max_image_height : 20f32,
-        pixel_side_length: 1f32
-    };
-
-    let (terrain_shaded_mesh, terrain_wireframe_mesh) = 
-        rtin_load_terrain(image_filename,
-            &rtin_params);
+    let image_filename = "terrain.heightmap";
 
-    let terrain_shaded_mesh_handle = meshes.add(terrain_shaded_mesh);
-    let terrain_wireframe_mesh_handle = meshes.add(terrain_wireframe_mesh);
-
-    terrain_mesh_res.shaded = terrain_shaded_mesh_handle;
-    terrain_mesh_res.wireframe = terrain_wireframe_mesh_handle;
-
-    let pipeline_handle = add_terrain_material(
-        pipelines, shaders, render_graph);
+    if let Err(err) = asset_server.watch_for_changes() {
+        warn!("terrain hot reloading disabled: {:?}", err);
+    }
 
+    // the terrain mesh is created once the heightmap is loaded
+    let terrain = Terrain {
+        heightmap: asset_server.load(image_filename),
+        error_threshold: 0.2,
+        load_options: TerrainImageLoadOptions {
+            max_image_height : 20f32,
+            pixel_side_length: 1f32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
 
     commands
         .spawn(MeshBundle {
-            mesh: terrain_mesh_res.shaded.clone(),
             render_pipelines: RenderPipelines::from_pipelines(vec![RenderPipeline::new(
-                pipeline_handle,
+                terrain_pipeline.handle.clone(),
             )]),
             transform: Transform::from_translation(Vec3::new(0.0, 0.0, 0.0)),
             ..Default::default()
-        }).with(Terrain{})
+        })
+        .with(terrain)
+        .with(terrain_materials.add(TerrainMaterial::default()))
         .spawn(LightBundle {
             transform: Transform::from_translation(Vec3::new(0.0, 4.0, 0.0)),
             ..Default::default()
@@ -100,9 +87,4 @@ fn setup(
 
     // add_axis_gizmo(commands, meshes, materials, 
     //     Transform::from_translation(Vec3::new(0f32, 0f32, 0f32)));
-
-    setup_ui(commands,
-        asset_server,
-        color_materials,
-        button_materials, rtin_params);
 }
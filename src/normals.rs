@@ -0,0 +1,172 @@
+use bevy::math::Vec3;
+use crate::heightmap::HeightSource;
+
+/// where terrain vertex normals are computed from
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum NormalSource {
+    /// area-weighted mean of the normals of the faces around each vertex
+    Mesh,
+    /// central differences on the full resolution heightmap, keeps
+    /// the shading detail that the simplified mesh drops
+    Heightmap,
+}
+
+impl Default for NormalSource {
+    fn default() -> Self {
+        NormalSource::Mesh
+    }
+}
+
+fn normalize_or_up(normal: Vec3) -> Vec3 {
+    let length = normal.length();
+    if length > 0.0 && length.is_finite() {
+        normal / length
+    } else {
+        Vec3::unit_y()
+    }
+}
+
+/// smooth vertex normals of a triangle list, each face contributes
+/// proportionally to its area.
+///
+/// The mesh is a heightfield, so faces are always oriented upwards
+/// whatever their winding
+pub fn smooth_vertex_normals(vertices: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::zero(); vertices.len()];
+
+    for triangle in indices.chunks_exact(3) {
+        let a = vertices[triangle[0] as usize];
+        let b = vertices[triangle[1] as usize];
+        let c = vertices[triangle[2] as usize];
+
+        // twice the face area long
+        let mut face_normal = (b - a).cross(c - a);
+        if face_normal.y < 0.0 {
+            face_normal = -face_normal;
+        }
+
+        for vertex_index in triangle {
+            normals[*vertex_index as usize] += face_normal;
+        }
+    }
+
+    normals.into_iter().map(normalize_or_up).collect()
+}
+
+/// normal of the heightmap at pixel (x, y), not normalized, with
+/// pixels one unit apart and heights as stored in the heightmap.
+///
+/// Uses central differences, one sided ones along the borders
+pub fn heightmap_normal_at<H: HeightSource + ?Sized>(heightmap: &H, x: u32, y: u32) -> Vec3 {
+    let x = x.min(heightmap.width() - 1);
+    let y = y.min(heightmap.height() - 1);
+
+    let x0 = x.saturating_sub(1);
+    let x1 = (x + 1).min(heightmap.width() - 1);
+    let y0 = y.saturating_sub(1);
+    let y1 = (y + 1).min(heightmap.height() - 1);
+
+    let dx = if x1 > x0 {
+        (heightmap.height_at(x1, y) - heightmap.height_at(x0, y)) / (x1 - x0) as f32
+    } else {
+        0.0
+    };
+    let dy = if y1 > y0 {
+        (heightmap.height_at(x, y1) - heightmap.height_at(x, y0)) / (y1 - y0) as f32
+    } else {
+        0.0
+    };
+
+    Vec3::new(-dx, 1.0, -dy)
+}
+
+/// laplacian of the heightmap at pixel (x, y), with pixels one unit
+/// apart. Border pixels reuse their inner neighbour
+pub fn heightmap_laplacian_at<H: HeightSource + ?Sized>(heightmap: &H, x: u32, y: u32) -> f32 {
+    let x = x.min(heightmap.width() - 1);
+    let y = y.min(heightmap.height() - 1);
+
+    let sample = |dx: i64, dy: i64| {
+        let sx = (x as i64 + dx).max(0).min(heightmap.width() as i64 - 1);
+        let sy = (y as i64 + dy).max(0).min(heightmap.height() as i64 - 1);
+        heightmap.height_at(sx as u32, sy as u32)
+    };
+
+    sample(-1, 0) + sample(1, 0) + sample(0, -1) + sample(0, 1) - 4.0 * sample(0, 0)
+}
+
+/// moves a normal computed on the heightmap grid to a terrain scaled by
+/// `pixel_side_length` horizontally and `max_image_height` vertically.
+///
+/// Normals transform with the inverse transpose of the scale,
+/// which keeps area weighting exact
+pub fn scale_grid_normal(normal: Vec3, pixel_side_length: f32, max_image_height: f32) -> Vec3 {
+    normalize_or_up(Vec3::new(
+        normal.x * max_image_height,
+        normal.y * pixel_side_length,
+        normal.z * max_image_height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::FnHeightSource;
+
+    fn assert_vec3_near(left: Vec3, right: Vec3) {
+        assert!((left - right).length() < 1e-5, "{:?} != {:?}", left, right);
+    }
+
+    #[test]
+    fn test_smooth_vertex_normals() {
+        let vertices = vec![
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            Vec3::new(1.0, 1.0, 1.0),
+        ];
+        // the two faces have opposite windings
+        let indices = vec![0, 1, 2, 1, 3, 2];
+
+        let expected = Vec3::new(-1.0, 1.0, 0.0).normalize();
+        for normal in smooth_vertex_normals(&vertices, &indices) {
+            assert_vec3_near(normal, expected);
+        }
+    }
+
+    #[test]
+    fn test_heightmap_normals_match_scaled_mesh_normals() {
+        let heightmap = FnHeightSource::new(4, 4, |x, y| x as f32 * 0.1 + y as f32 * 0.2);
+        let (pixel_side_length, max_image_height) = (2.0, 10.0);
+
+        let vertices: Vec<Vec3> = (0..4u32).flat_map(|y| (0..4u32).map(move |x| (x, y)))
+            .map(|(x, y)| Vec3::new(
+                x as f32 * pixel_side_length,
+                heightmap.height_at(x, y) * max_image_height,
+                y as f32 * pixel_side_length))
+            .collect();
+        let indices = vec![0, 5, 1, 0, 4, 5, 10, 15, 11, 10, 14, 15];
+        let mesh_normals = smooth_vertex_normals(&vertices, &indices);
+
+        for (x, y) in &[(0, 0), (1, 1), (3, 3)] {
+            let normal = scale_grid_normal(
+                heightmap_normal_at(&heightmap, *x, *y),
+                pixel_side_length, max_image_height);
+            assert_vec3_near(normal, mesh_normals[(y * 4 + x) as usize]);
+        }
+    }
+
+    #[test]
+    fn test_heightmap_laplacian() {
+        let heightmap = FnHeightSource::new(5, 5, |x, y| (x * x + y) as f32);
+        assert_eq!(heightmap_laplacian_at(&heightmap, 2, 2), 2.0);
+        // clamped borders flatten the parabola
+        assert_eq!(heightmap_laplacian_at(&heightmap, 0, 2), 1.0);
+    }
+
+    #[test]
+    fn test_flat_and_degenerate_normals() {
+        let heightmap = FnHeightSource::new(1, 1, |_, _| 0.5);
+        assert_eq!(heightmap_normal_at(&heightmap, 0, 0), Vec3::unit_y());
+        assert_eq!(scale_grid_normal(Vec3::new(-1.0, 1.0, 0.0), 1.0, 0.0), Vec3::unit_y());
+    }
+}
@@ -1,10 +1,42 @@
 use bitintr::Lzcnt;
+use std::{fmt::Debug, hash::Hash, ops::{Add, BitAnd, BitOr, Not, Shl, Sub}};
 
 extern crate nalgebra as na;
 use na::Vector2;
 
 pub type BinId = u32;
 
+/// Integer type a bin id is stored in. A bin id needs one bit 
+/// per level plus two, `u32` covers grids up to 2^15 per side
+pub trait BinIdInt: 
+    Copy + Debug + Eq + Ord + Hash + MSBScan +
+    Add<Output = Self> + Sub<Output = Self> + Shl<u32, Output = Self> +
+    BitAnd<Output = Self> + BitOr<Output = Self> + Not<Output = Self> {
+
+    fn zero() -> Self;
+    fn one() -> Self;
+    fn from_u32(value: u32) -> Self;
+    /// truncates values that don't fit
+    fn as_u32(self) -> u32;
+    fn as_usize(self) -> usize;
+}
+
+impl BinIdInt for u32 {
+    fn zero() -> Self { 0 }
+    fn one() -> Self { 1 }
+    fn from_u32(value: u32) -> Self { value }
+    fn as_u32(self) -> u32 { self }
+    fn as_usize(self) -> usize { self as usize }
+}
+
+impl BinIdInt for u64 {
+    fn zero() -> Self { 0 }
+    fn one() -> Self { 1 }
+    fn from_u32(value: u32) -> Self { value as u64 }
+    fn as_u32(self) -> u32 { self as u32 }
+    fn as_usize(self) -> usize { self as usize }
+}
+
 pub type Vec2u32 = Vector2<u32>;
 
 pub type TriangleU32 = (Vec2u32, Vec2u32, Vec2u32);
@@ -14,31 +46,35 @@ pub type TriangleU32 = (Vec2u32, Vec2u32, Vec2u32);
 ///
 /// ```
 /// # use bevy_terrain::rtin::*;
-/// assert_eq!(get_index_level_start(0), 0b0);
-/// assert_eq!(get_index_level_start(1), 0b10);
-/// assert_eq!(get_index_level_start(1), 2);
-/// assert_eq!(get_index_level_start(2), 0b110);
-/// assert_eq!(get_index_level_start(2), 6);
-/// assert_eq!(get_index_level_start(3), 0b1110);
-/// assert_eq!(get_index_level_start(3), 14);
-/// ```
-pub fn get_index_level_start(level: u32) -> u32 {
-    ( (2 << level) - 1 ) & (!1u32)
+/// assert_eq!(get_index_level_start::<u32>(0), 0b0);
+/// assert_eq!(get_index_level_start::<u32>(1), 0b10);
+/// assert_eq!(get_index_level_start::<u32>(1), 2);
+/// assert_eq!(get_index_level_start::<u32>(2), 0b110);
+/// assert_eq!(get_index_level_start::<u32>(2), 6);
+/// assert_eq!(get_index_level_start::<u32>(3), 0b1110);
+/// assert_eq!(get_index_level_start::<u32>(3), 14);
+/// assert_eq!(get_index_level_start::<u64>(3), 14);
+/// assert_eq!(get_index_level_start::<u64>(40), (1 << 41) - 2);
+/// ```
+pub fn get_index_level_start<T: BinIdInt>(level: u32) -> T {
+    ( (T::from_u32(2) << level) - T::one() ) & !T::one()
 }
 
 /// returns the relative triangle index within its level
 ///
 /// ```
 /// # use bevy_terrain::rtin::*;
-/// assert_eq!(bin_id_to_index_in_level(0b10), 0);
-/// assert_eq!(bin_id_to_index_in_level(0b11), 1);
-/// assert_eq!(bin_id_to_index_in_level(0b100), 0);
-/// assert_eq!(bin_id_to_index_in_level(0b101), 1);
-/// assert_eq!(bin_id_to_index_in_level(0b110), 2);
-/// assert_eq!(bin_id_to_index_in_level(0b111), 3);
-/// ```
-pub fn bin_id_to_index_in_level(bin_id: u32) -> u32 {
-    bin_id - (1 << (bin_id.msbscan()-1) )
+/// assert_eq!(bin_id_to_index_in_level(0b10_u32), 0);
+/// assert_eq!(bin_id_to_index_in_level(0b11_u32), 1);
+/// assert_eq!(bin_id_to_index_in_level(0b100_u32), 0);
+/// assert_eq!(bin_id_to_index_in_level(0b101_u32), 1);
+/// assert_eq!(bin_id_to_index_in_level(0b110_u32), 2);
+/// assert_eq!(bin_id_to_index_in_level(0b111_u32), 3);
+/// assert_eq!(bin_id_to_index_in_level(0b111_u64), 3);
+/// assert_eq!(bin_id_to_index_in_level((1_u64 << 40) + 5), 5);
+/// ```
+pub fn bin_id_to_index_in_level<T: BinIdInt>(bin_id: T) -> T {
+    bin_id - (T::one() << (bin_id.msbscan().as_u32()-1) )
 }
 
 pub trait MSBScan {
@@ -55,6 +91,8 @@ pub trait MSBScan {
     /// assert_eq!(0b0000_0000_u32.msbscan(), 0_u32);
     /// assert_eq!(0b0000_0001_u32.msbscan(), 1_u32);
     /// assert_eq!(0b0001_1001_u32.msbscan(), 5_u32);
+    /// assert_eq!(0b0001_1001_u64.msbscan(), 5_u64);
+    /// assert_eq!((1_u64 << 40).msbscan(), 41_u64);
     /// ```
     fn msbscan(self) -> Self;
 
@@ -68,6 +106,14 @@ impl MSBScan for u32 {
 
 }
 
+impl MSBScan for u64 {
+    
+    fn msbscan(self) -> u64 {
+        64 - self.lzcnt()
+    }
+
+}
+
 /// level 0: 
 ///
 ///   +----+
@@ -92,23 +138,25 @@ impl MSBScan for u32 {
 ///
 /// ```
 /// # use bevy_terrain::rtin::*;
-/// assert_eq!(bin_id_to_index(0b10), 0);
-/// assert_eq!(bin_id_to_index(0b11), 1);
-/// assert_eq!(bin_id_to_index(0b100), 2);
-/// assert_eq!(bin_id_to_index(0b111), 5);
-/// assert_eq!(bin_id_to_index(0b1011), 9);
+/// assert_eq!(bin_id_to_index(0b10_u32), 0);
+/// assert_eq!(bin_id_to_index(0b11_u32), 1);
+/// assert_eq!(bin_id_to_index(0b100_u32), 2);
+/// assert_eq!(bin_id_to_index(0b111_u32), 5);
+/// assert_eq!(bin_id_to_index(0b1011_u32), 9);
+/// assert_eq!(bin_id_to_index(0b1011_u64), 9);
+/// assert_eq!(bin_id_to_index(1_u64 << 40), (1 << 40) - 2);
 /// ```
 ///
-pub fn bin_id_to_index(bin_id: u32) -> u32 {
+pub fn bin_id_to_index<T: BinIdInt>(bin_id: T) -> T {
     let level = bin_id_to_level(bin_id);
-    let index_level_start = get_index_level_start(level);
+    let index_level_start = get_index_level_start::<T>(level);
     let index_in_level = bin_id_to_index_in_level(bin_id);
 
     index_level_start + index_in_level
 }
 
 
-pub fn get_triangle_children_indices(bin_id: u32) -> (u32, u32) {
+pub fn get_triangle_children_indices<T: BinIdInt>(bin_id: T) -> (T, T) {
     let (right_index, left_index) = 
         get_triangle_children_bin_ids(bin_id);
     (bin_id_to_index(right_index), bin_id_to_index(left_index))
@@ -116,47 +164,258 @@ pub fn get_triangle_children_indices(bin_id: u32) -> (u32, u32) {
 
 /// ```
 /// # use bevy_terrain::rtin::*;
-/// assert_eq!(get_triangle_children_bin_ids(0b10), (0b100, 0b110));
-/// assert_eq!(get_triangle_children_bin_ids(0b1010), (0b10010, 0b11010));
+/// assert_eq!(get_triangle_children_bin_ids(0b10_u32), (0b100, 0b110));
+/// assert_eq!(get_triangle_children_bin_ids(0b1010_u32), (0b10010, 0b11010));
+/// assert_eq!(get_triangle_children_bin_ids(0b1010_u64), (0b10010, 0b11010));
+/// assert_eq!(get_triangle_children_bin_ids(1_u64 << 40), (1 << 41, 3 << 40));
 /// ```
-pub fn get_triangle_children_bin_ids(bin_id: u32) -> (u32, u32) {
+pub fn get_triangle_children_bin_ids<T: BinIdInt>(bin_id: T) -> (T, T) {
     let level = bin_id_to_level(bin_id);
+    // the children of the deepest u32 level overflow, see `RtinGrid::has_children`
     let right_bin_id = 
-        bin_id + (1 << (level+2) ) - (1 << (level+1) );
+        bin_id - (T::one() << (level+1) ) + (T::one() << (level+2) );
     let left_bin_id = 
-        bin_id + (1 << (level+2) );
+        bin_id + (T::one() << (level+2) );
     (right_bin_id, left_bin_id)
 }
 
-/// convert the binary id of the triangle to the index
+/// whether the triangle is split further in a grid of `grid_size`
+/// vertices per side. Check it before asking for the children of a
+/// triangle: the children of the deepest `u32` leaves overflow
 ///
 /// ```
 /// # use bevy_terrain::rtin::*;
-/// assert_eq!(index_to_bin_id(0), 0b10);
-/// assert_eq!(index_to_bin_id(1), 0b11);
-/// assert_eq!(index_to_bin_id(2), 0b100);
-/// assert_eq!(index_to_bin_id(5), 0b111);
-/// assert_eq!(index_to_bin_id(9), 0b1011);
-/// ```
-pub fn index_to_bin_id(index: u32) -> u32 {
-    let mut level = 0;
-    let mut index_level_start = 0;
-
-    for i in 0..32 {
-        let new_index_level_start = get_index_level_start(i);
-        if index >= new_index_level_start {
-            level = i;
-            index_level_start = new_index_level_start;
+/// assert!(triangle_has_children(0b10_u32, 3));
+/// assert!(triangle_has_children(0b110_u32, 3));
+/// assert!(!triangle_has_children(0b1110_u32, 3));
+/// // levels 29 and 30 of the largest grid
+/// assert!(triangle_has_children(1_u32 << 30, (1 << 15) + 1));
+/// assert!(!triangle_has_children(1_u32 << 31, (1 << 15) + 1));
+/// assert!(!triangle_has_children(u32::MAX, (1 << 15) + 1));
+/// ```
+pub fn triangle_has_children<T: BinIdInt>(bin_id: T, grid_size: u32) -> bool {
+    // leaves are half pixel triangles
+    let leaf_level = (grid_size - 1).trailing_zeros() * 2;
+    bin_id_to_level(bin_id) < leaf_level
+}
+
+/// the triangle split into `bin_id` and its sibling, `None` for the two
+/// level 0 triangles
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(get_triangle_parent_bin_id(0b10_u32), None);
+/// assert_eq!(get_triangle_parent_bin_id(0b100_u32), Some(0b10));
+/// assert_eq!(get_triangle_parent_bin_id(0b11010_u32), Some(0b1010));
+/// assert_eq!(get_triangle_parent_bin_id(3_u64 << 40), Some(1 << 40));
+/// ```
+pub fn get_triangle_parent_bin_id<T: BinIdInt>(bin_id: T) -> Option<T> {
+    if bin_id_to_level(bin_id) == 0 {
+        None
+    } else {
+        Some(RtinGrid::parent_of(bin_id).0)
+    }
+}
+
+/// the other child of the parent of `bin_id`, `None` for the two
+/// level 0 triangles
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(get_triangle_sibling_bin_id(0b11_u32), None);
+/// assert_eq!(get_triangle_sibling_bin_id(0b100_u32), Some(0b110));
+/// assert_eq!(get_triangle_sibling_bin_id(0b11010_u32), Some(0b10010));
+/// assert_eq!(get_triangle_sibling_bin_id(3_u64 << 40), Some(1 << 41));
+/// ```
+pub fn get_triangle_sibling_bin_id<T: BinIdInt>(bin_id: T) -> Option<T> {
+    get_triangle_parent_bin_id(bin_id).map(|parent_bin_id| {
+        let (right_child_bin_id, left_child_bin_id) = 
+            get_triangle_children_bin_ids(parent_bin_id);
+        if bin_id == right_child_bin_id { left_child_bin_id } else { right_child_bin_id }
+    })
+}
+
+/// triangles sharing the edges of a triangle at the same level,
+/// `None` on the borders of the grid
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TriangleNeighbours<T> {
+    /// across the hypotenuse a, b
+    pub base: Option<T>,
+    /// across the c, a edge, which is its b, c edge
+    pub left: Option<T>,
+    /// across the b, c edge, which is its c, a edge
+    pub right: Option<T>,
+}
+
+/// Neighbours of a triangle, following its splits from the level 0
+/// triangles. The edges of a child are the sibling edge, half of the
+/// parent hypotenuse and a whole leg of the parent, so its neighbours
+/// are the sibling and children of the parent neighbours
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// # use nalgebra::Vector2;
+/// let grid = RtinGrid::new(9);
+/// let to_i64 = |v: Vec2u32| v.map(|v| v as i64);
+/// // mirrored through the middle of p, q
+/// let mirrored = |v: Vector2<i64>, p: Vector2<i64>, q: Vector2<i64>| p + q - v;
+///
+/// for index in 0..grid.number_of_triangles() {
+///     let bin_id = index_to_bin_id(index);
+///     let (a, b, c) = grid.triangle_coords(bin_id);
+///     let (a, b, c) = (to_i64(a), to_i64(b), to_i64(c));
+///     let neighbours = get_triangle_neighbours_bin_ids(bin_id);
+///
+///     let expected_neighbours = [
+///         (neighbours.base, (b, a, mirrored(c, a, b))),
+///         (neighbours.left, (mirrored(b, c, c), a, c)),
+///         (neighbours.right, (b, mirrored(a, c, c), c)),
+///     ];
+///     for (neighbour, (na, nb, nc)) in expected_neighbours.iter() {
+///         let inside = [na, nb, nc].iter().all(|v| v.iter().all(|v| *v >= 0 && *v <= 8));
+///         match neighbour {
+///             Some(neighbour_bin_id) => {
+///                 let (ma, mb, mc) = grid.triangle_coords(*neighbour_bin_id);
+///                 assert_eq!((to_i64(ma), to_i64(mb), to_i64(mc)), (*na, *nb, *nc));
+///                 assert_eq!(bin_id_to_level(*neighbour_bin_id), bin_id_to_level(bin_id));
+///             },
+///             None => assert!(!inside),
+///         }
+///     }
+/// }
+/// ```
+pub fn get_triangle_neighbours_bin_ids<T: BinIdInt>(bin_id: T) -> TriangleNeighbours<T> {
+    let root_bin_id = (T::one() << 1) | (bin_id & T::one());
+    let other_root_bin_id = if root_bin_id == T::from_u32(0b10) {
+        T::from_u32(0b11)
+    } else {
+        T::from_u32(0b10)
+    };
+
+    let mut triangle_bin_id = root_bin_id;
+    let mut neighbours = TriangleNeighbours {
+        base: Some(other_root_bin_id),
+        left: None,
+        right: None,
+    };
+
+    let right_child = |bin_id| get_triangle_children_bin_ids(bin_id).0;
+    let left_child = |bin_id| get_triangle_children_bin_ids(bin_id).1;
+
+    for level in 1..=bin_id_to_level(bin_id) {
+        let (right_child_bin_id, left_child_bin_id) = 
+            get_triangle_children_bin_ids(triangle_bin_id);
+
+        // the bit under the leading one is the last split
+        if bin_id & (T::one() << level) != T::zero() {
+            neighbours = TriangleNeighbours {
+                base: neighbours.left.map(right_child),
+                left: Some(right_child_bin_id),
+                right: neighbours.base.map(right_child),
+            };
+            triangle_bin_id = left_child_bin_id;
         } else {
-            break;
+            neighbours = TriangleNeighbours {
+                base: neighbours.right.map(left_child),
+                left: neighbours.base.map(left_child),
+                right: Some(left_child_bin_id),
+            };
+            triangle_bin_id = right_child_bin_id;
         }
     }
 
-    ( 1 << (level+1) ) + (index - index_level_start)
+    debug_assert!(triangle_bin_id == bin_id);
+    neighbours
 }
 
-pub fn bin_id_to_level(bin_id: u32) -> u32 {
-    bin_id.msbscan() - 2
+/// The triangle at the same level sharing the hypotenuse, forming a
+/// diamond which is split and merged at once. `None` when the
+/// hypotenuse is on the border of the grid
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(get_triangle_base_neighbour_bin_id(0b10_u32), Some(0b11));
+/// assert_eq!(get_triangle_base_neighbour_bin_id(0b100_u32), None);
+/// assert_eq!(get_triangle_base_neighbour_bin_id(0b1000_u32), Some(0b1110));
+/// assert_eq!(get_triangle_base_neighbour_bin_id(0b1000_u64), Some(0b1110));
+///
+/// let grid = RtinGrid::new(9);
+/// for index in 0..grid.number_of_triangles() {
+///     let bin_id = index_to_bin_id(index);
+///     let (a, b, c) = grid.triangle_coords(bin_id);
+///     // mirrored across the hypotenuse
+///     let mirrored_c = (a + b).map(|v| v as i64) - c.map(|v| v as i64);
+///     let inside = mirrored_c.iter().all(|v| *v >= 0 && *v <= 8);
+///
+///     match get_triangle_base_neighbour_bin_id(bin_id) {
+///         Some(neighbour_bin_id) => {
+///             assert!(inside);
+///             let (na, nb, nc) = grid.triangle_coords(neighbour_bin_id);
+///             assert_eq!((na, nb), (b, a));
+///             assert_eq!(nc.map(|v| v as i64), mirrored_c);
+///         },
+///         None => assert!(!inside),
+///     }
+/// }
+/// ```
+pub fn get_triangle_base_neighbour_bin_id<T: BinIdInt>(bin_id: T) -> Option<T> {
+    get_triangle_neighbours_bin_ids(bin_id).base
+}
+
+/// the triangle at the same level sharing the c, a edge
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(get_triangle_left_neighbour_bin_id(0b10_u32), None);
+/// assert_eq!(get_triangle_left_neighbour_bin_id(0b100_u32), Some(0b111));
+/// assert_eq!(get_triangle_left_neighbour_bin_id(0b1000_u32), None);
+/// assert_eq!(get_triangle_left_neighbour_bin_id(0b1100_u32), Some(0b1000));
+/// assert_eq!(get_triangle_left_neighbour_bin_id(0b1100_u64), Some(0b1000));
+/// ```
+pub fn get_triangle_left_neighbour_bin_id<T: BinIdInt>(bin_id: T) -> Option<T> {
+    get_triangle_neighbours_bin_ids(bin_id).left
+}
+
+/// the triangle at the same level sharing the b, c edge
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(get_triangle_right_neighbour_bin_id(0b10_u32), None);
+/// assert_eq!(get_triangle_right_neighbour_bin_id(0b110_u32), Some(0b101));
+/// assert_eq!(get_triangle_right_neighbour_bin_id(0b1100_u32), None);
+/// assert_eq!(get_triangle_right_neighbour_bin_id(0b1000_u32), Some(0b1100));
+/// assert_eq!(get_triangle_right_neighbour_bin_id(0b1000_u64), Some(0b1100));
+/// ```
+pub fn get_triangle_right_neighbour_bin_id<T: BinIdInt>(bin_id: T) -> Option<T> {
+    get_triangle_neighbours_bin_ids(bin_id).right
+}
+
+/// convert the binary id of the triangle to the index
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(index_to_bin_id(0_u32), 0b10);
+/// assert_eq!(index_to_bin_id(1_u32), 0b11);
+/// assert_eq!(index_to_bin_id(2_u32), 0b100);
+/// assert_eq!(index_to_bin_id(5_u32), 0b111);
+/// assert_eq!(index_to_bin_id(9_u32), 0b1011);
+/// assert_eq!(index_to_bin_id(9_u64), 0b1011);
+/// assert_eq!(index_to_bin_id((1_u64 << 40) - 2), 1 << 40);
+/// ```
+pub fn index_to_bin_id<T: BinIdInt>(index: T) -> T {
+    // level l starts at index 2^(l+1) - 2 and at bin id 2^(l+1),
+    // so bin ids are just shifted indices
+    index + T::from_u32(2)
+}
+
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(bin_id_to_level(0b11_u32), 0);
+/// assert_eq!(bin_id_to_level(0b1011_u32), 2);
+/// assert_eq!(bin_id_to_level(1_u64 << 40), 39);
+/// ```
+pub fn bin_id_to_level<T: BinIdInt>(bin_id: T) -> u32 {
+    bin_id.msbscan().as_u32() - 2
 }
 
 /// Get the rect triangle basis middle point coordinate
@@ -172,17 +431,68 @@ pub fn bin_id_to_level(bin_id: u32) -> u32 {
 /// # use bevy_terrain::rtin::*;
 /// let n_tiles = 4;
 /// assert_eq!(
-///    pixel_coords_for_triangle_mid_point(0b10_1110, n_tiles),  
+///    pixel_coords_for_triangle_mid_point(0b10_1110_u32, n_tiles),  
+///    Vec2u32::new(1, 2) );
+/// assert_eq!(
+///    pixel_coords_for_triangle_mid_point(0b10_1110_u64, n_tiles),  
 ///    Vec2u32::new(1, 2) );
 /// ```
 ///
-pub fn pixel_coords_for_triangle_mid_point(bin_id: u32, grid_size: u32) -> Vec2u32 {
+pub fn pixel_coords_for_triangle_mid_point<T: BinIdInt>(bin_id: T, grid_size: u32) -> Vec2u32 {
     let triangle_coords = get_triangle_coords(bin_id, grid_size);
     let mid_point = (triangle_coords.0 + triangle_coords.1) / 2;
 
     Vec2u32::new(mid_point[0], mid_point[1])
 }
 
+/// Ends of the hypotenuse of the triangles split at a grid point, `None`
+/// for the grid corners which are never split points. Diamond partners
+/// share their hypotenuse so there is a single answer
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// assert_eq!(hypotenuse_for_mid_point(Vec2u32::new(2, 2), 5),
+///     Some((Vec2u32::new(0, 0), Vec2u32::new(4, 4))));
+/// assert_eq!(hypotenuse_for_mid_point(Vec2u32::new(2, 0), 5),
+///     Some((Vec2u32::new(0, 0), Vec2u32::new(4, 0))));
+/// assert_eq!(hypotenuse_for_mid_point(Vec2u32::new(3, 1), 5),
+///     Some((Vec2u32::new(2, 2), Vec2u32::new(4, 0))));
+/// assert_eq!(hypotenuse_for_mid_point(Vec2u32::new(4, 0), 5), None);
+///
+/// let grid = RtinGrid::new(9);
+/// for index in 0..get_index_level_start::<u32>(5) {
+///     let (a, b, _) = grid.triangle_coords(index_to_bin_id(index));
+///     let (end_a, end_b) = hypotenuse_for_mid_point((a + b) / 2, 9).unwrap();
+///     assert!((end_a, end_b) == (a, b) || (end_b, end_a) == (a, b));
+/// }
+/// ```
+pub fn hypotenuse_for_mid_point(point: Vec2u32, grid_size: u32) -> Option<(Vec2u32, Vec2u32)> {
+    let (x, y) = (point[0], point[1]);
+    if x | y == 0 {
+        return None;
+    }
+
+    // half the hypotenuse projected on the axes
+    let k = 1 << (x | y).trailing_zeros();
+    if k >= grid_size - 1 {
+        return None;
+    }
+
+    let hypotenuse = match ((x / k) % 2 == 1, (y / k) % 2 == 1) {
+        // center of a square, the diagonals alternate like a checkerboard
+        (true, true) => if ((x - k) / (2*k) + (y - k) / (2*k)) % 2 == 0 {
+            ((x - k, y - k), (x + k, y + k))
+        } else {
+            ((x - k, y + k), (x + k, y - k))
+        },
+        (true, false) => ((x - k, y), (x + k, y)),
+        _ => ((x, y - k), (x, y + k)),
+    };
+
+    let ((ax, ay), (bx, by)) = hypotenuse;
+    Some((Vec2u32::new(ax, ay), Vec2u32::new(bx, by)))
+}
+
 /// 
 /// vertex C always on the right-angle corner
 /// a, b, c ordering always clockwise
@@ -227,15 +537,17 @@ pub fn pixel_coords_for_triangle_mid_point(bin_id: u32, grid_size: u32) -> Vec2u
 /// ```
 /// # use bevy_terrain::rtin::*;
 /// let n_tiles = 4;
-/// assert_eq!(get_triangle_coords(0b11, n_tiles),  
+/// assert_eq!(get_triangle_coords(0b11_u32, n_tiles),  
 ///    (Vec2u32::new(0, 0), Vec2u32::new(4, 4), Vec2u32::new(4, 0)) );
-/// assert_eq!(get_triangle_coords(0b110, n_tiles),  
+/// assert_eq!(get_triangle_coords(0b110_u32, n_tiles),  
 ///    (Vec2u32::new(0, 4), Vec2u32::new(4, 4), Vec2u32::new(2, 2)) );
-/// assert_eq!(get_triangle_coords(0b1_1110, n_tiles),  
+/// assert_eq!(get_triangle_coords(0b1_1110_u32, n_tiles),  
 ///    (Vec2u32::new(2, 4), Vec2u32::new(2, 2), Vec2u32::new(1, 3)) );
+/// assert_eq!(get_triangle_coords(0b1_1110_u64, n_tiles),  
+///    get_triangle_coords(0b1_1110_u32, n_tiles));
 /// ```
 ///
-pub fn get_triangle_coords(bin_id: u32, grid_size: u32) -> TriangleU32 {
+pub fn get_triangle_coords<T: BinIdInt>(bin_id: T, grid_size: u32) -> TriangleU32 {
     let mut a = Vec2u32::new(0, 0);
     let mut b = Vec2u32::new(0, 0);
     let mut c = Vec2u32::new(0, 0);
@@ -288,6 +600,151 @@ pub fn get_triangle_coords(bin_id: u32, grid_size: u32) -> TriangleU32 {
     (a, b, c)
 }
 
+/// corners of the two level 0 triangles, see `get_triangle_coords`
+fn root_triangle_coords<T: BinIdInt>(bin_id: T, grid_size: u32) -> TriangleU32 {
+    let last = grid_size - 1;
+    if bin_id & T::one() != T::zero() {
+        (Vec2u32::new(0, 0), Vec2u32::new(last, last), Vec2u32::new(last, 0))
+    } else {
+        (Vec2u32::new(last, last), Vec2u32::new(0, 0), Vec2u32::new(0, last))
+    }
+}
+
+/// levels of triangles stored by an `RtinGrid`, about 16 MiB of
+/// coordinates, the whole table of grids up to a side of 1024
+pub const RTIN_GRID_TABLE_LEVELS: u32 = 20;
+
+/// Corners of the triangles of a grid, computed once and looked up
+/// by bin id instead of walking the partition steps every time.
+///
+/// Only the a and b corners of the triangles with children are stored,
+/// c is the hypotenuse midpoint rotated by a right angle. The table stops
+/// at `RTIN_GRID_TABLE_LEVELS`, leaves and deeper triangles are split from
+/// their deepest stored ancestor on the fly
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// let grid = RtinGrid::new(5);
+/// assert_eq!(grid.triangle_coords(0b1_1110_u32), get_triangle_coords(0b1_1110_u32, 5));
+/// assert_eq!(grid.triangle_coords(0b1_1110_u64), get_triangle_coords(0b1_1110_u32, 5));
+/// for index in 0..grid.number_of_triangles() {
+///     let bin_id = index_to_bin_id(index);
+///     assert_eq!(grid.triangle_coords(bin_id), get_triangle_coords(bin_id, 5));
+/// }
+///
+/// // the table stops at level 19, the leaves are at level 22
+/// let grid = RtinGrid::new(2049);
+/// for index in &[(1u64 << 21) - 3, (1 << 21) - 2, (1 << 22) + 5, grid.number_of_triangles() - 1] {
+///     let bin_id = index_to_bin_id(*index);
+///     assert_eq!(grid.triangle_coords(bin_id), get_triangle_coords(bin_id, 2049));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RtinGrid {
+    grid_size: u32,
+    /// ax, ay, bx, by of the triangles of the stored levels, by index
+    coords: Vec::<u16>,
+}
+
+impl RtinGrid {
+    /// tables of a grid of `grid_size` vertices per side, one
+    /// more than a power of two up to 2^15
+    pub fn new(grid_size: u32) -> Self {
+        let side = grid_size.saturating_sub(1);
+        assert!(side.is_power_of_two() && side <= 1 << 15, 
+            "invalid RTIN grid size {}", grid_size);
+
+        // triangles of every level but the last, made of leaves, 
+        // up to the table levels
+        let number_of_parents = (side as u64 * side as u64 * 2 - 2)
+            .min((1 << (RTIN_GRID_TABLE_LEVELS + 1)) - 2);
+        let mut coords = Vec::with_capacity(number_of_parents as usize * 4);
+
+        for index in 0..number_of_parents {
+            let bin_id = index_to_bin_id(index);
+            let (a, b, _) = if bin_id_to_level(bin_id) == 0 {
+                root_triangle_coords(bin_id, grid_size)
+            } else {
+                let (parent_bin_id, is_left) = RtinGrid::parent_of(bin_id);
+                let parent_index = bin_id_to_index(parent_bin_id).as_usize() * 4;
+                let parent = RtinGrid::corners(&coords[parent_index..parent_index+4]);
+                RtinGrid::child_coords(parent, is_left)
+            };
+
+            coords.extend_from_slice(&[a[0] as u16, a[1] as u16, b[0] as u16, b[1] as u16]);
+        }
+
+        RtinGrid {
+            grid_size,
+            coords,
+        }
+    }
+
+    pub fn grid_size(&self) -> u32 {
+        self.grid_size
+    }
+
+    /// number of triangles of all levels, leaves included
+    pub fn number_of_triangles(&self) -> u64 {
+        let side = (self.grid_size - 1) as u64;
+        side * side * 4 - 2
+    }
+
+    /// same as `triangle_has_children(bin_id, self.grid_size())`
+    pub fn has_children<T: BinIdInt>(&self, bin_id: T) -> bool {
+        triangle_has_children(bin_id, self.grid_size)
+    }
+
+    /// same as `get_triangle_coords(bin_id, self.grid_size())`
+    pub fn triangle_coords<T: BinIdInt>(&self, bin_id: T) -> TriangleU32 {
+        let index = bin_id_to_index(bin_id).as_usize() * 4;
+
+        if index < self.coords.len() {
+            RtinGrid::corners(&self.coords[index..index+4])
+        } else if bin_id_to_level(bin_id) == 0 {
+            root_triangle_coords(bin_id, self.grid_size)
+        } else {
+            let (parent_bin_id, is_left) = RtinGrid::parent_of(bin_id);
+            RtinGrid::child_coords(self.triangle_coords(parent_bin_id), is_left)
+        }
+    }
+
+    /// same as `pixel_coords_for_triangle_mid_point(bin_id, self.grid_size())`
+    pub fn triangle_mid_point<T: BinIdInt>(&self, bin_id: T) -> Vec2u32 {
+        let (a, b, _) = self.triangle_coords(bin_id);
+        (a + b) / 2
+    }
+
+    /// parent bin id, and whether `bin_id` is its left child
+    fn parent_of<T: BinIdInt>(bin_id: T) -> (T, bool) {
+        let msb = bin_id.msbscan().as_u32() - 1;
+        let is_left = bin_id & (T::one() << (msb - 1)) != T::zero();
+        ((bin_id & !(T::one() << msb)) | (T::one() << (msb - 1)), is_left)
+    }
+
+    fn child_coords(parent: TriangleU32, is_left: bool) -> TriangleU32 {
+        let (a, b, c) = parent;
+        if is_left {
+            (c, a, (a+b) / 2)
+        } else {
+            (b, c, (a+b) / 2)
+        }
+    }
+
+    fn corners(ab: &[u16]) -> TriangleU32 {
+        let (ax, ay, bx, by) = (ab[0] as i64, ab[1] as i64, ab[2] as i64, ab[3] as i64);
+        // twice the hypotenuse midpoint is always on the grid
+        let cx = (ax + bx - ay + by) / 2;
+        let cy = (ay + by + ax - bx) / 2;
+
+        (
+            Vec2u32::new(ab[0] as u32, ab[1] as u32),
+            Vec2u32::new(ab[2] as u32, ab[3] as u32),
+            Vec2u32::new(cx as u32, cy as u32)
+        )
+    }
+}
+
 #[derive(Eq, PartialEq, Debug)]
 pub enum PartitionStep {
     TopRight,
@@ -300,27 +757,29 @@ pub enum PartitionStep {
 ///
 /// ```
 /// # use bevy_terrain::rtin::*;
-/// assert_eq!(bin_id_to_partition_steps(0b10), [PartitionStep::BottomLeft]);
-/// assert_eq!(bin_id_to_partition_steps(0b11), [PartitionStep::TopRight]);
-/// assert_eq!(bin_id_to_partition_steps(0b110), 
+/// assert_eq!(bin_id_to_partition_steps(0b10_u32), [PartitionStep::BottomLeft]);
+/// assert_eq!(bin_id_to_partition_steps(0b11_u32), [PartitionStep::TopRight]);
+/// assert_eq!(bin_id_to_partition_steps(0b110_u32), 
 ///   [PartitionStep::BottomLeft, PartitionStep::Left]);
-/// assert_eq!(bin_id_to_partition_steps(0b10110), 
+/// assert_eq!(bin_id_to_partition_steps(0b10110_u32), 
 ///   [PartitionStep::BottomLeft, PartitionStep::Left, PartitionStep::Left,
 ///    PartitionStep::Right]);
+/// assert_eq!(bin_id_to_partition_steps(0b10110_u64), 
+///   bin_id_to_partition_steps(0b10110_u32));
 /// ```
 ///
-pub fn bin_id_to_partition_steps(bin_id: u32) -> Vec::<PartitionStep> {
+pub fn bin_id_to_partition_steps<T: BinIdInt>(bin_id: T) -> Vec::<PartitionStep> {
     let mut steps = Vec::new();
     let triangle_level = bin_id_to_level(bin_id);
 
-    if bin_id & 1 > 0 {
+    if bin_id & T::one() != T::zero() {
         steps.push(PartitionStep::TopRight);
     } else {
         steps.push(PartitionStep::BottomLeft);
     }
 
     for i in 1..(triangle_level+1) {
-       if bin_id & (1 << i) > 0 {
+       if bin_id & (T::one() << i) != T::zero() {
         steps.push(PartitionStep::Left);
        } else {
         steps.push(PartitionStep::Right);
@@ -328,4 +787,73 @@ pub fn bin_id_to_partition_steps(bin_id: u32) -> Vec::<PartitionStep> {
     }
 
     steps
-}
\ No newline at end of file
+}
+
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum TriangleExtentOverlap {
+    Inside,
+    Outside,
+    Crossing
+}
+
+/// classify a triangle against the rectangle going from the origin 
+/// to `extent`. Triangles only touching the rectangle border are
+/// considered outside
+///
+/// ```
+/// # use bevy_terrain::rtin::*;
+/// let n_tiles = 4;
+/// let extent = Vec2u32::new(3, 2);
+/// assert_eq!(
+///    triangle_extent_overlap(get_triangle_coords(0b10_u32, n_tiles+1), Vec2u32::new(4, 4)),  
+///    TriangleExtentOverlap::Inside);
+/// assert_eq!(
+///    triangle_extent_overlap(get_triangle_coords(0b10_u32, n_tiles+1), extent),  
+///    TriangleExtentOverlap::Crossing);
+/// assert_eq!(
+///    triangle_extent_overlap(get_triangle_coords(0b1_1110_u32, n_tiles+1), extent),  
+///    TriangleExtentOverlap::Outside);
+/// ```
+///
+pub fn triangle_extent_overlap(triangle: TriangleU32, extent: Vec2u32) -> TriangleExtentOverlap {
+    let vertices = [triangle.0, triangle.1, triangle.2];
+
+    let inside = vertices.iter().all(|v| v[0] <= extent[0] && v[1] <= extent[1]);
+    if inside {
+        return TriangleExtentOverlap::Inside;
+    }
+
+    let to_i64 = |v: &Vec2u32| (v[0] as i64, v[1] as i64);
+    let triangle_points: Vec<(i64, i64)> = vertices.iter().map(to_i64).collect();
+    let extent_points = [
+        (0, 0), 
+        (extent[0] as i64, 0), 
+        (0, extent[1] as i64), 
+        (extent[0] as i64, extent[1] as i64)
+    ];
+
+    let mut axes = vec![(1i64, 0i64), (0, 1)];
+    for i in 0..3 {
+        let (px, py) = triangle_points[i];
+        let (qx, qy) = triangle_points[(i+1) % 3];
+        axes.push((py - qy, qx - px));
+    }
+
+    let project = |points: &[(i64, i64)], axis: (i64, i64)| {
+        let values = points.iter().map(|p| p.0 * axis.0 + p.1 * axis.1);
+        (values.clone().min().unwrap(), values.max().unwrap())
+    };
+
+    // separating axis test, touching shapes do not overlap
+    let separated = axes.iter().any(|axis| {
+        let (triangle_min, triangle_max) = project(&triangle_points, *axis);
+        let (extent_min, extent_max) = project(&extent_points, *axis);
+        triangle_max <= extent_min || extent_max <= triangle_min
+    });
+
+    if separated {
+        TriangleExtentOverlap::Outside
+    } else {
+        TriangleExtentOverlap::Crossing
+    }
+}
@@ -0,0 +1,149 @@
+use bevy::math::Vec3;
+
+/// Downward strips along the four borders of a terrain mesh, hiding the
+/// cracks between neighbour tiles meshed with different accuracy
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkirtOptions {
+    /// skirt depth over the largest error of the mesh triangles
+    pub error_factor: f32,
+    /// skirt depth when the mesh is more accurate than that,
+    /// in heightmap units like the errors
+    pub min_depth: f32,
+}
+
+impl Default for SkirtOptions {
+    fn default() -> Self {
+        SkirtOptions {
+            error_factor: 2.0,
+            min_depth: 0.01,
+        }
+    }
+}
+
+impl SkirtOptions {
+    /// depth below the border vertices, in heightmap units
+    pub fn depth(&self, max_error: f32) -> f32 {
+        (max_error * self.error_factor).max(self.min_depth)
+    }
+}
+
+/// skirt geometry to append to a mesh
+#[derive(Debug, Default, PartialEq)]
+pub struct Skirt {
+    /// the border vertex each skirt vertex is lowered from,
+    /// skirt vertices are numbered after the mesh ones
+    pub top_vertices: Vec::<u32>,
+    /// skirt triangles, facing out of the mesh
+    pub indices: Vec::<u32>,
+}
+
+/// Vertices on the borders of the rectangle covered by a grid mesh,
+/// going along the north (lowest z), east, south then west border
+fn border_loop(vertices: &[Vec3]) -> Vec::<u32> {
+    if vertices.is_empty() {
+        return Vec::new();
+    }
+
+    let (min, max) = vertices.iter().fold(
+        (vertices[0], vertices[0]), |(min, max), vertex| (min.min(*vertex), max.max(*vertex)));
+
+    let border = |on_border: &dyn Fn(&Vec3) -> bool, key: &dyn Fn(&Vec3) -> f32| {
+        let mut border: Vec::<u32> = (0..vertices.len() as u32)
+            .filter(|index| on_border(&vertices[*index as usize]))
+            .collect();
+        border.sort_by(|a, b| key(&vertices[*a as usize])
+            .partial_cmp(&key(&vertices[*b as usize])).unwrap());
+        border
+    };
+
+    let mut border_loop = border(&|vertex| vertex.z == min.z, &|vertex| vertex.x);
+    border_loop.extend(border(&|vertex| vertex.x == max.x, &|vertex| vertex.z));
+    border_loop.extend(border(&|vertex| vertex.z == max.z, &|vertex| -vertex.x));
+    border_loop.extend(border(&|vertex| vertex.x == min.x, &|vertex| -vertex.z));
+
+    // each border starts on the corner the previous one ends with
+    border_loop.dedup();
+    if border_loop.len() > 1 && border_loop.first() == border_loop.last() {
+        border_loop.pop();
+    }
+
+    border_loop
+}
+
+/// Skirt of a grid mesh with y up, such as the RTIN or the full
+/// resolution meshes. Every border edge gets a quad down to the
+/// lowered copies of its vertices
+pub fn mesh_skirt(vertices: &[Vec3]) -> Skirt {
+    let top_vertices = border_loop(vertices);
+    if top_vertices.len() < 2 {
+        return Skirt::default();
+    }
+
+    let first_skirt_vertex = vertices.len() as u32;
+    let loop_len = top_vertices.len();
+    let mut indices = Vec::<u32>::with_capacity(loop_len * 6);
+
+    for i in 0..loop_len {
+        let j = (i + 1) % loop_len;
+        let (top_a, top_b) = (top_vertices[i], top_vertices[j]);
+        let (bottom_a, bottom_b) = (first_skirt_vertex + i as u32, first_skirt_vertex + j as u32);
+
+        indices.extend([top_a, top_b, bottom_a].iter());
+        indices.extend([top_b, bottom_b, bottom_a].iter());
+    }
+
+    Skirt {
+        top_vertices,
+        indices,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grid_vertices(side: u32) -> Vec::<Vec3> {
+        (0..=side)
+            .flat_map(|z| (0..=side).map(move |x| Vec3::new(x as f32, 0.0, z as f32)))
+            .collect()
+    }
+
+    #[test]
+    fn test_border_loop() {
+        // 0 1 2
+        // 3 4 5
+        // 6 7 8
+        assert_eq!(border_loop(&grid_vertices(2)), vec![0, 1, 2, 5, 8, 7, 6, 3]);
+        assert_eq!(border_loop(&[]), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn test_skirt_faces_out() {
+        let mut vertices = grid_vertices(4);
+        let skirt = mesh_skirt(&vertices);
+        assert_eq!(skirt.top_vertices.len(), 16);
+        assert_eq!(skirt.indices.len(), 16 * 6);
+
+        let center = Vec3::new(2.0, 0.0, 2.0);
+        let bottom_vertices: Vec::<Vec3> = skirt.top_vertices.iter()
+            .map(|index| vertices[*index as usize] - Vec3::new(0.0, 1.0, 0.0))
+            .collect();
+        vertices.extend(bottom_vertices);
+
+        for triangle in skirt.indices.chunks(3) {
+            let (a, b, c) = (vertices[triangle[0] as usize],
+                vertices[triangle[1] as usize], vertices[triangle[2] as usize]);
+            let normal = (b - a).cross(c - a);
+            let outwards = (a + b + c) / 3.0 - center;
+            assert!(normal.y.abs() < 1e-6);
+            assert!(normal.dot(outwards) > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_skirt_depth() {
+        let options = SkirtOptions { error_factor: 2.0, min_depth: 0.05 };
+        assert_eq!(options.depth(0.0), 0.05);
+        assert_eq!(options.depth(0.1), 0.2);
+    }
+}
@@ -1,27 +1,25 @@
-use anyhow::Result;
 use std::vec::Vec;
-use crate::terrain_common::TerrainImageLoadOptions;
-use image::ImageBuffer;
+use crate::{heightmap::{HeightChannel, HeightEncoding, HeightSource, Heightmap}, normals::smooth_vertex_normals, skirt::mesh_skirt, terrain_common::{TerrainError, TerrainImageLoadOptions}, terrain_rtin::heightmap_pixel_uv};
+use bevy::math::Vec3;
 use bevy_render::{
     pipeline::PrimitiveTopology,
     mesh::{Mesh, VertexAttributeValues, Indices},
 };
-use image::Luma;
 
 
-pub fn terrain_example() -> Mesh {
+pub fn terrain_example() -> Result<Mesh, TerrainError> {
     let options = TerrainImageLoadOptions {
         max_image_height : 1f32,
         pixel_side_length : 1f32,
+        ..Default::default()
     };
 
     let filename = "terrain.png";
 
-    let mesh = load_terrain_bitmap(filename, options);
-    mesh.unwrap()
+    load_terrain_bitmap(filename, HeightEncoding::Normalized, HeightChannel::Luminance, options)
 }
 
-fn sample_vertex_height(cy: i32, cx: i32, heightmap: &ImageBuffer<Luma<u16>, Vec::<u16>>) -> f32 {
+fn sample_vertex_height<H: HeightSource + ?Sized>(cy: i32, cx: i32, heightmap: &H) -> f32 {
     let mut cnt = 0;
     let mut height = 0.0;
 
@@ -35,8 +33,7 @@ fn sample_vertex_height(cy: i32, cx: i32, heightmap: &ImageBuffer<Luma<u16>, Vec
                || sx >= heightmap.width() as i32 {
                 continue;
             } else {
-                height += heightmap.get_pixel(
-                    sx as u32, sy as u32).0[0] as f32 * 1.0f32 / std::u16::MAX as f32;
+                height += heightmap.height_at(sx as u32, sy as u32);
                 cnt += 1;
             }
         }
@@ -45,33 +42,43 @@ fn sample_vertex_height(cy: i32, cx: i32, heightmap: &ImageBuffer<Luma<u16>, Vec
     height / cnt as f32
 }
 
-fn load_terrain_bitmap(filename: &str, options: TerrainImageLoadOptions) -> Result<Mesh> {
-    let terrain_bitmap = image::open(filename)?;
+pub fn load_terrain_bitmap(filename: &str, encoding: HeightEncoding, channel: HeightChannel, 
+    options: TerrainImageLoadOptions) -> Result<Mesh, TerrainError> {
+    let heightmap = Heightmap::open(filename, encoding, channel)?;
     let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
 
-    let heightmap = terrain_bitmap.as_luma16().unwrap();
-
     let mut vertices : Vec::<[f32; 3]> = Vec::new();
-    let mut normals : Vec::<[f32; 3]> = Vec::new();
     let mut indices : Vec::<u32> = Vec::new();
 
     let vertex_number = ( (heightmap.height() + 1) * 
         (heightmap.width() + 1) ) as usize; 
 
     vertices.resize(vertex_number, [0.0f32, 0.0f32, 0.0f32]);
-    normals.resize(vertex_number, [0.0f32, 1.0f32, 0.0f32]);
-    let uvs = vec![[0.0, 0.0, 0.0]; vertices.len()];
+    let mut uvs : Vec::<[f32; 2]> = Vec::new();
+    uvs.resize(vertex_number, [0.0f32, 0.0f32]);
 
 
     let mut vertex_index = 0;
     for cy in 0..(heightmap.height() as i32 +1) {
         for cx in 0..(heightmap.width() as i32 +1) {
-            let height = sample_vertex_height(cy, cx, heightmap);
+            let height = sample_vertex_height(cy, cx, &heightmap);
             // println!("sampled height at y={:>3} x={:>3}  = {:>4}", cy, cx, height);
 
             vertices[vertex_index] = [cx as f32 * options.pixel_side_length,
               height * options.max_image_height, 
               cy as f32 * options.pixel_side_length];
+
+            // vertices sit on the pixel corners, half a pixel before the
+            // pixel centers where the RTIN mesher puts its vertices
+            let (pixel_x, pixel_y) = (cx as f32 - 0.5, cy as f32 - 0.5);
+            uvs[vertex_index] = match options.uv_tile_size {
+                Some(tile_size) => [pixel_x * options.pixel_side_length / tile_size,
+                    pixel_y * options.pixel_side_length / tile_size],
+                None => {
+                    let uv = heightmap_pixel_uv(&heightmap, pixel_x.max(0.0), pixel_y.max(0.0));
+                    [uv.x, uv.y]
+                }
+            };
             vertex_index += 1;
         }
     }
@@ -103,6 +110,29 @@ fn load_terrain_bitmap(filename: &str, options: TerrainImageLoadOptions) -> Resu
 
     assert!(indices.len() as u32 /  3 == 2  * heightmap.height() * (heightmap.width()) );
 
+    let vertices_3d : Vec::<Vec3> = vertices.iter()
+        .map(|vertex| Vec3::new(vertex[0], vertex[1], vertex[2]))
+        .collect();
+    let mut normals : Vec::<[f32; 3]> = smooth_vertex_normals(&vertices_3d, &indices)
+        .iter()
+        .map(|normal| [normal.x, normal.y, normal.z])
+        .collect();
+
+    // the full resolution mesh has no error, only the minimum depth applies
+    if let Some(skirt_options) = &options.skirt {
+        let skirt = mesh_skirt(&vertices_3d);
+        let depth = skirt_options.depth(0f32) * options.max_image_height;
+
+        for top_vertex in &skirt.top_vertices {
+            let top_vertex = *top_vertex as usize;
+            let [x, y, z] = vertices[top_vertex];
+            vertices.push([x, y - depth, z]);
+            normals.push(normals[top_vertex]);
+            uvs.push(uvs[top_vertex]);
+        }
+        indices.extend(skirt.indices);
+    }
+
 
     mesh.set_attribute(
         Mesh::ATTRIBUTE_POSITION,
@@ -112,7 +142,7 @@ fn load_terrain_bitmap(filename: &str, options: TerrainImageLoadOptions) -> Resu
         VertexAttributeValues::Float3(normals));
     mesh.set_attribute(
         Mesh::ATTRIBUTE_UV_0,
-         VertexAttributeValues::Float3(uvs));
+         VertexAttributeValues::Float2(uvs));
     mesh.set_indices(Some(Indices::U32(indices)));
 
 
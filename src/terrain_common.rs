@@ -1,14 +1,158 @@
 use bevy::prelude::*;
-pub struct Terrain {}
+use std::{fmt, sync::Arc};
+use crate::{color_ramp::ColorRamp, heightmap::Heightmap, hillshade::HillshadeOptions, normals::NormalSource, skirt::SkirtOptions, terrain_rtin::{RtinBudget, RtinView}, vertex_colorizer::VertexColorizer};
 
+/// A terrain meshed from its heightmap with RTIN. 
+/// The entity is re-meshed whenever this component changes
 #[derive(Default)]
+pub struct Terrain {
+    pub heightmap: Handle<Heightmap>,
+    pub error_threshold: f32,
+    pub load_options: TerrainImageLoadOptions,
+    /// refine the terrain for the camera instead of the error threshold,
+    /// re-meshing it as the camera moves
+    pub screen_space_error: Option<ScreenSpaceErrorOptions>,
+    /// mesh as accurately as the budget allows instead of
+    /// using the error threshold
+    pub budget: Option<RtinBudget>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScreenSpaceErrorOptions {
+    /// largest error allowed once projected on screen, in pixels
+    pub max_pixel_error: f32,
+    /// how far the camera moves, in world units, before re-meshing
+    pub remesh_distance: f32,
+}
+
+impl Default for ScreenSpaceErrorOptions {
+    fn default() -> Self {
+        ScreenSpaceErrorOptions {
+            max_pixel_error: 2.0,
+            remesh_distance: 1.0,
+        }
+    }
+}
+
+/// the view a screen space error terrain was last meshed for
+pub struct TerrainView {
+    pub view: RtinView,
+    pub camera_translation: Vec3,
+}
+
+/// How a heightmap is meshed. How its heights are decoded is up to
+/// whatever loads it, e.g. the .heightmap description or `TerrainPlugin`
+#[derive(Default, Clone)]
 pub struct TerrainImageLoadOptions {
+    /// world space height of a heightmap value of 1. Integer images are
+    /// normalized in [0, 1], so this is the height of their brightest
+    /// pixel. Float images and elevation tiles keep their own units,
+    /// e.g. meters, and are meshed at their absolute heights with 1
     pub max_image_height : f32,
-    pub pixel_side_length : f32
+    pub pixel_side_length : f32,
+    pub normal_source : NormalSource,
+    /// world space side of one texture repeat, the UVs span
+    /// the whole heightmap from 0 to 1 when not set
+    pub uv_tile_size : Option<f32>,
+    /// the default ramp expects normalized heights, stretch it over
+    /// `Heightmap::height_range` for float images and elevation tiles
+    pub color_ramp : ColorRamp,
+    /// replaces the height based `color_ramp` when set
+    pub colorizer : Option<Arc<dyn VertexColorizer>>,
+    /// hillshade of the full resolution heightmap multiplied
+    /// into the vertex colors
+    pub hillshade : Option<HillshadeOptions>,
+    /// downward strips along the borders, hiding the cracks
+    /// between tiles meshed with different errors
+    pub skirt : Option<SkirtOptions>,
 }
 
-#[derive(Default)]
-pub struct TerrainMeshResource {
+/// meshes generated for a `Terrain` entity, the entity
+/// `Handle<Mesh>` points to one of them
+#[derive(Default, Clone)]
+pub struct TerrainMeshes {
     pub shaded: Handle<Mesh>,
     pub wireframe: Handle<Mesh>,
 }
+
+/// errors returned while loading a terrain from a heightmap
+#[derive(Debug)]
+pub enum TerrainError {
+    /// the heightmap file could not be read
+    Io(std::io::Error),
+    /// the heightmap could not be decoded
+    Decode(image::ImageError),
+    /// the pixel format of the heightmap is not supported
+    UnsupportedPixelFormat(image::ColorType),
+    /// the heightmap is too large to be meshed
+    InvalidDimensions { width: u32, height: u32 },
+    /// the heightmap has no pixel
+    EmptyImage,
+    /// a color ramp entry could not be parsed, lines start from 1
+    InvalidColorRamp { line: usize },
+    /// the color ramp has no color stop
+    EmptyColorRamp,
+    /// a heightmap description line could not be parsed, lines start
+    /// from 1 and line 0 means the image is missing
+    InvalidHeightmapDescription { line: usize },
+}
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TerrainError::Io(err) => 
+                write!(f, "cannot read heightmap: {}", err),
+            TerrainError::Decode(err) => 
+                write!(f, "cannot decode heightmap: {}", err),
+            TerrainError::UnsupportedPixelFormat(color_type) => 
+                write!(f, "unsupported heightmap pixel format {:?}", color_type),
+            TerrainError::InvalidDimensions { width, height } => 
+                write!(f, "invalid heightmap dimensions {}x{}", width, height),
+            TerrainError::EmptyImage => 
+                write!(f, "heightmap is empty"),
+            TerrainError::InvalidColorRamp { line } => 
+                write!(f, "invalid color ramp entry at line {}", line),
+            TerrainError::EmptyColorRamp => 
+                write!(f, "color ramp has no color"),
+            TerrainError::InvalidHeightmapDescription { line: 0 } => 
+                write!(f, "heightmap description has no image"),
+            TerrainError::InvalidHeightmapDescription { line } => 
+                write!(f, "invalid heightmap description entry at line {}", line),
+        }
+    }
+}
+
+impl std::error::Error for TerrainError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TerrainError::Io(err) => Some(err),
+            TerrainError::Decode(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for TerrainError {
+    fn from(err: std::io::Error) -> Self {
+        TerrainError::Io(err)
+    }
+}
+
+impl From<tiff::TiffError> for TerrainError {
+    fn from(err: tiff::TiffError) -> Self {
+        match err {
+            tiff::TiffError::IoError(err) => TerrainError::Io(err),
+            err => TerrainError::Decode(image::ImageError::Decoding(
+                image::error::DecodingError::new(image::ImageFormat::Tiff.into(), err))),
+        }
+    }
+}
+
+impl From<image::ImageError> for TerrainError {
+    fn from(err: image::ImageError) -> Self {
+        match err {
+            image::ImageError::IoError(err) => TerrainError::Io(err),
+            err => TerrainError::Decode(err),
+        }
+    }
+}
@@ -9,29 +9,69 @@ use bevy::{
     },
 };
 
-#[derive(RenderResources, Default, TypeUuid)]
+/// Vertex colored terrain lit by a directional sun and a
+/// hemispheric ambient light, blending from the ground color for
+/// faces looking down to the sky color for faces looking up.
+///
+/// Vertices are moved towards their morph height by the morph factor,
+/// from 0 for the mesh as built to 1 for the mesh where every diamond
+/// whose children are all selected is merged back. Only the midpoints of
+/// those diamonds move, the other vertices keep their height. Animating
+/// it over time or distance hides the popping when the terrain is re-meshed
+#[derive(RenderResources, TypeUuid)]
 #[uuid = "0320b9b8-b3a3-4baa-8bfa-c94008177b17"]
 pub struct TerrainMaterial {
+    /// direction pointing towards the sun, in world space
+    pub sun_direction: Vec3,
+    pub sun_color: Color,
+    pub ambient_sky_color: Color,
+    pub ambient_ground_color: Color,
+    pub morph_factor: f32,
+}
+
+impl Default for TerrainMaterial {
+    fn default() -> Self {
+        TerrainMaterial {
+            sun_direction: Vec3::new(0.4, 1.0, 0.3).normalize(),
+            sun_color: Color::rgb(0.8, 0.8, 0.75),
+            ambient_sky_color: Color::rgb(0.3, 0.33, 0.4),
+            ambient_ground_color: Color::rgb(0.1, 0.09, 0.08),
+            morph_factor: 0.0,
+        }
+    }
 }
 
 impl TerrainMaterial {
     pub const ATTRIBUTE_COLOR: &'static str = "Vertex_Color";
+    /// the vertex height in the coarser mesh, see `TerrainMeshData`
+    pub const ATTRIBUTE_MORPH_HEIGHT: &'static str = "Vertex_Morph_Height";
 }
 
 const VERTEX_SHADER: &str = r#"
 #version 450
 layout(location = 0) in vec3 Vertex_Position;
-layout(location = 1) in vec3 Vertex_Color;
+layout(location = 1) in vec3 Vertex_Normal;
+layout(location = 2) in vec3 Vertex_Color;
+layout(location = 3) in float Vertex_Morph_Height;
 layout(location = 0) out vec3 v_color;
+layout(location = 1) out vec3 v_normal;
 layout(set = 0, binding = 0) uniform Camera {
     mat4 ViewProj;
 };
 layout(set = 1, binding = 0) uniform Transform {
     mat4 Model;
 };
+layout(set = 1, binding = 5) uniform TerrainMaterial_morph_factor {
+    float morph_factor;
+};
 void main() {
-    gl_Position = ViewProj * Model * vec4(Vertex_Position, 1.0);
+    vec3 position = Vertex_Position;
+    position.y = mix(position.y, Vertex_Morph_Height, morph_factor);
+    gl_Position = ViewProj * Model * vec4(position, 1.0);
     v_color = Vertex_Color;
+    // terrains are only scaled through the heightmap load options,
+    // so the model matrix has no non uniform scale
+    v_normal = mat3(Model) * Vertex_Normal;
 }
 "#;
 
@@ -39,17 +79,62 @@ const FRAGMENT_SHADER: &str = r#"
 #version 450
 layout(location = 0) out vec4 o_Target;
 layout(location = 0) in vec3 v_color;
+layout(location = 1) in vec3 v_normal;
+layout(set = 1, binding = 1) uniform TerrainMaterial_sun_direction {
+    vec3 sun_direction;
+};
+layout(set = 1, binding = 2) uniform TerrainMaterial_sun_color {
+    vec4 sun_color;
+};
+layout(set = 1, binding = 3) uniform TerrainMaterial_ambient_sky_color {
+    vec4 ambient_sky_color;
+};
+layout(set = 1, binding = 4) uniform TerrainMaterial_ambient_ground_color {
+    vec4 ambient_ground_color;
+};
 void main() {
-    o_Target = vec4(v_color, 1.0);
-    // o_Target = vec4(1.0, 1.0, 1.0, 1.0);
+    vec3 normal = normalize(v_normal);
+
+    float lambert = max(dot(normal, normalize(sun_direction)), 0.0);
+    float sky_weight = 0.5 + 0.5 * normal.y;
+    vec3 ambient = mix(ambient_ground_color.rgb, ambient_sky_color.rgb, sky_weight);
+
+    o_Target = vec4(v_color * (ambient + sun_color.rgb * lambert), 1.0);
 }
 "#;
 
+/// the terrain render pipeline, created once the render graph is available
+pub struct TerrainPipeline {
+    pub handle: Handle<PipelineDescriptor>,
+}
+
+impl FromResources for TerrainPipeline {
+    fn from_resources(resources: &Resources) -> Self {
+        let mut pipelines = resources.get_mut::<Assets<PipelineDescriptor>>().unwrap();
+        let mut shaders = resources.get_mut::<Assets<Shader>>().unwrap();
+        let mut render_graph = resources.get_mut::<RenderGraph>().unwrap();
+
+        TerrainPipeline {
+            handle: add_terrain_material_to_render_graph(
+                &mut pipelines, &mut shaders, &mut render_graph)
+        }
+    }
+}
+
 pub fn add_terrain_material(
     mut pipelines: ResMut<Assets<PipelineDescriptor>>,
     mut shaders: ResMut<Assets<Shader>>,
     mut render_graph: ResMut<RenderGraph>
 ) -> Handle<PipelineDescriptor> {
+    add_terrain_material_to_render_graph(
+        &mut pipelines, &mut shaders, &mut render_graph)
+}
+
+pub fn add_terrain_material_to_render_graph(
+    pipelines: &mut Assets<PipelineDescriptor>,
+    shaders: &mut Assets<Shader>,
+    render_graph: &mut RenderGraph
+) -> Handle<PipelineDescriptor> {
 
         // Create a new shader pipeline
     let pipeline_handle = pipelines.add(PipelineDescriptor::default_config(ShaderStages {
@@ -0,0 +1,53 @@
+use bevy::prelude::*;
+use crate::{
+    heightmap::{HeightChannel, HeightEncoding, Heightmap},
+    heightmap_loader::{HeightmapImages, HeightmapLoader, heightmap_image_watcher_system},
+    terrain_material::{TerrainMaterial, TerrainPipeline},
+    terrain_rtin::{RtinTerrainCache, rtin_heightmap_asset_event_system, rtin_terrain_cache_eviction_system, rtin_terrain_changed_system, rtin_terrain_view_system},
+    terrain_roam::rtin_roam_terrain_system,
+    terrain_tiles::rtin_terrain_tiles_system,
+};
+
+/// Registers the terrain material, its render pipeline, the heightmap
+/// asset loader, the cache of heightmap errors and the systems
+/// re-meshing terrains.
+///
+/// Must be added after `DefaultPlugins`
+#[derive(Default)]
+pub struct TerrainPlugin {
+    /// channel of the .heightmap files not setting one and of the TIFFs
+    pub height_channel: HeightChannel,
+    /// encoding of the .heightmap files not setting one and of the TIFFs
+    pub height_encoding: HeightEncoding,
+    /// reload the heightmaps of the .heightmap files when their image
+    /// changes on disk. The asset server watches the other heightmap
+    /// files once `AssetServer::watch_for_changes` is called
+    pub watch_heightmap_images: bool,
+}
+
+impl Plugin for TerrainPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        let heightmap_images = HeightmapImages::default();
+
+        app.add_asset::<TerrainMaterial>()
+            .add_asset::<Heightmap>()
+            .add_asset_loader(HeightmapLoader {
+                height_channel: self.height_channel,
+                height_encoding: self.height_encoding,
+                images: heightmap_images.clone(),
+            })
+            .add_resource(heightmap_images)
+            .init_resource::<TerrainPipeline>()
+            .init_resource::<RtinTerrainCache>()
+            .add_system(rtin_terrain_changed_system.system())
+            .add_system(rtin_heightmap_asset_event_system.system())
+            .add_system(rtin_terrain_view_system.system())
+            .add_system(rtin_terrain_tiles_system.system())
+            .add_system(rtin_roam_terrain_system.system())
+            .add_system(rtin_terrain_cache_eviction_system.system());
+
+        if self.watch_heightmap_images {
+            app.add_system(heightmap_image_watcher_system.system());
+        }
+    }
+}
@@ -0,0 +1,1023 @@
+use bevy::prelude::*;
+use bevy::{render::camera::PerspectiveProjection, window::Windows};
+use bevy_render::mesh::{Indices, VertexAttributeValues};
+use std::{cmp::{Ordering, Reverse}, collections::{BinaryHeap, HashMap, HashSet}, ops::Range, sync::Arc};
+use crate::{
+    heightmap::{HeightSource, Heightmap},
+    normals::{heightmap_laplacian_at, heightmap_normal_at},
+    rtin::{
+        BinId, RtinGrid, TriangleExtentOverlap, TriangleU32, Vec2u32,
+        get_triangle_base_neighbour_bin_id, get_triangle_children_bin_ids,
+        get_triangle_parent_bin_id, triangle_extent_overlap,
+    },
+    terrain_common::{Terrain, TerrainImageLoadOptions, TerrainMeshes, TerrainView},
+    terrain_material::TerrainMaterial,
+    terrain_rtin::{
+        RtinSplitCandidate, RtinTerrain, RtinTerrainCache, RtinView, TerrainMeshData,
+        heightmap_pixel_uv, rtin_diamond_morph_height, rtin_grid_extent,
+        rtin_make_terrain_meshes_from_data, rtin_store_terrain_meshes,
+        sample_heightmap_height_corner_mean, triangle_coords_errors_vec_index,
+    },
+    terrain_tiles::TerrainTile,
+};
+
+/// splits and merges done by an `RtinRoam` update
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct RtinRoamUpdate {
+    /// triangles split, forced splits included
+    pub splits: usize,
+    /// diamonds merged back into their two parents
+    pub merges: usize,
+    /// split and merge candidates checked against the accuracy
+    pub checks: usize,
+}
+
+/// what an `RtinRoam` was last refined for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RoamAccuracy {
+    Threshold(f32),
+    View(RtinView),
+}
+
+/// a checked candidate, checked again once the camera travelled up to `travel`
+#[derive(Debug, Clone, Copy)]
+struct RoamRecheck {
+    travel: f64,
+    candidate: RtinSplitCandidate,
+}
+
+impl PartialEq for RoamRecheck {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RoamRecheck {}
+
+impl PartialOrd for RoamRecheck {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RoamRecheck {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // travels are never NaN
+        self.travel.partial_cmp(&other.travel).unwrap_or(Ordering::Equal)
+            .then(self.candidate.bin_id.cmp(&other.candidate.bin_id))
+    }
+}
+
+/// the candidates of `deferred` whose travel is reached
+fn pop_due_rechecks(deferred: &mut BinaryHeap<Reverse<RoamRecheck>>, travel: f64) 
+    -> Vec<RtinSplitCandidate> {
+    let mut due = Vec::new();
+    while deferred.peek().map_or(false, |Reverse(recheck)| recheck.travel <= travel) {
+        due.push(deferred.pop().unwrap().0.candidate);
+    }
+    due
+}
+
+/// a grid point used by the triangles of an `RtinRoam`
+#[derive(Debug, Clone, Copy)]
+struct RoamVertex {
+    point: Vec2u32,
+    /// number of slots using the vertex
+    references: u32,
+    /// a triangle split from the diamond the vertex is the midpoint of,
+    /// to find its morph height
+    diamond_child: Option<BinId>,
+}
+
+fn extend_range(range: &mut Option<Range<usize>>, index: usize) {
+    *range = Some(match range.take() {
+        Some(range) => range.start.min(index)..range.end.max(index + 1),
+        None => index..index + 1,
+    });
+}
+
+/// A triangulation kept from frame to frame and refined ROAM style,
+/// splitting and merging only the triangles whose accuracy changed
+/// instead of selecting all the triangles again.
+///
+/// Triangles are split along with their base neighbour, splitting a
+/// coarser base neighbour first, so the triangulation stays crack free.
+/// The split and merge candidates stay queued across updates. Once
+/// checked, a candidate waits until the camera moved far enough for its
+/// accuracy to change, so an update only checks the candidates of the
+/// region the camera move affects. Every triangle overlapping the
+/// heightmap owns a slot of the index buffer and its corners are pooled
+/// vertices, so a change only rewrites the slots and vertices involved
+pub struct RtinRoam {
+    grid: Arc<RtinGrid>,
+    extent: Vec2u32,
+    /// current triangles, with their slot when they overlap the heightmap
+    triangles: HashMap<BinId, Option<usize>>,
+    /// triangle of every slot, `None` for the free slots
+    slots: Vec<Option<BinId>>,
+    free_slots: Vec<usize>,
+    /// slots changed since the last `take_dirty_slots`
+    dirty_slots: Option<Range<usize>>,
+    /// pool vertex of the grid points used by the slots, by `y * grid_size + x`
+    vertex_indices: HashMap<u32, usize>,
+    /// `None` for the free vertices
+    vertices: Vec<Option<RoamVertex>>,
+    free_vertices: Vec<usize>,
+    /// vertices changed since the last `take_dirty_vertices`
+    dirty_vertices: Option<Range<usize>>,
+    /// triangles inserted since they were last queued
+    new_triangles: Vec<BinId>,
+    /// triangles that may split, largest error first
+    split_queue: BinaryHeap<RtinSplitCandidate>,
+    /// diamonds that may merge, by one of their two triangles, lowest error first
+    merge_queue: BinaryHeap<Reverse<RtinSplitCandidate>>,
+    /// checked split candidates, accurate until the camera travels further
+    deferred_splits: BinaryHeap<Reverse<RoamRecheck>>,
+    /// checked merge candidates, not accurate until the camera travels further
+    deferred_merges: BinaryHeap<Reverse<RoamRecheck>>,
+    /// queued or deferred split candidates
+    queued_splits: HashSet<BinId>,
+    /// queued or deferred merge candidates
+    queued_merges: HashSet<BinId>,
+    /// camera travel over the updates since `accuracy` last changed
+    travel: f64,
+    accuracy: Option<RoamAccuracy>,
+}
+
+impl RtinRoam {
+    /// the two level 0 triangles covering the heightmap
+    pub fn new<H: HeightSource + ?Sized>(heightmap: &H, grid: Arc<RtinGrid>) -> Self {
+        let mut roam = RtinRoam {
+            extent: rtin_grid_extent(heightmap),
+            grid,
+            triangles: HashMap::new(),
+            slots: Vec::new(),
+            free_slots: Vec::new(),
+            dirty_slots: None,
+            vertex_indices: HashMap::new(),
+            vertices: Vec::new(),
+            free_vertices: Vec::new(),
+            dirty_vertices: None,
+            new_triangles: Vec::new(),
+            split_queue: BinaryHeap::new(),
+            merge_queue: BinaryHeap::new(),
+            deferred_splits: BinaryHeap::new(),
+            deferred_merges: BinaryHeap::new(),
+            queued_splits: HashSet::new(),
+            queued_merges: HashSet::new(),
+            travel: 0.0,
+            accuracy: None,
+        };
+
+        roam.insert_triangle(0b10);
+        roam.insert_triangle(0b11);
+        roam
+    }
+
+    pub fn grid(&self) -> &Arc<RtinGrid> {
+        &self.grid
+    }
+
+    /// the current triangles, sorted by bin id
+    pub fn triangle_bin_ids(&self) -> Vec::<BinId> {
+        let mut triangle_bin_ids: Vec::<BinId> = self.triangles.keys().copied().collect();
+        triangle_bin_ids.sort();
+        triangle_bin_ids
+    }
+
+    pub fn contains(&self, bin_id: BinId) -> bool {
+        self.triangles.contains_key(&bin_id)
+    }
+
+    fn can_split(&self, bin_id: BinId) -> bool {
+        self.grid.has_children(bin_id)
+    }
+
+    fn vertex_id(&self, point: Vec2u32) -> u32 {
+        point[1] * self.grid.grid_size() + point[0]
+    }
+
+    fn acquire_vertex(&mut self, point: Vec2u32) -> usize {
+        let vertex_id = self.vertex_id(point);
+        if let Some(index) = self.vertex_indices.get(&vertex_id) {
+            self.vertices[*index].as_mut().unwrap().references += 1;
+            return *index;
+        }
+
+        let index = match self.free_vertices.pop() {
+            Some(index) => index,
+            None => {
+                self.vertices.push(None);
+                self.vertices.len() - 1
+            }
+        };
+        self.vertices[index] = Some(RoamVertex { point, references: 1, diamond_child: None });
+        self.vertex_indices.insert(vertex_id, index);
+        extend_range(&mut self.dirty_vertices, index);
+        index
+    }
+
+    /// the vertex index while other slots still use it
+    fn release_vertex(&mut self, point: Vec2u32) -> Option<usize> {
+        let vertex_id = self.vertex_id(point);
+        let index = *self.vertex_indices.get(&vertex_id)?;
+        let vertex = self.vertices[index].as_mut().unwrap();
+        vertex.references -= 1;
+
+        if vertex.references == 0 {
+            self.vertices[index] = None;
+            self.vertex_indices.remove(&vertex_id);
+            self.free_vertices.push(index);
+            None
+        } else {
+            Some(index)
+        }
+    }
+
+    fn insert_triangle(&mut self, bin_id: BinId) {
+        let triangle_coords = self.grid.triangle_coords(bin_id);
+        let slot = if triangle_extent_overlap(triangle_coords, self.extent)
+                != TriangleExtentOverlap::Outside {
+            let slot = match self.free_slots.pop() {
+                Some(slot) => slot,
+                None => {
+                    self.slots.push(None);
+                    self.slots.len() - 1
+                }
+            };
+            self.slots[slot] = Some(bin_id);
+            extend_range(&mut self.dirty_slots, slot);
+
+            self.acquire_vertex(triangle_coords.0);
+            self.acquire_vertex(triangle_coords.1);
+            // the midpoint of the diamond split, its morph height may change
+            let mid_point_index = self.acquire_vertex(triangle_coords.2);
+            self.vertices[mid_point_index].as_mut().unwrap().diamond_child = Some(bin_id);
+            extend_range(&mut self.dirty_vertices, mid_point_index);
+            Some(slot)
+        } else {
+            None
+        };
+
+        self.triangles.insert(bin_id, slot);
+        self.new_triangles.push(bin_id);
+    }
+
+    fn remove_triangle(&mut self, bin_id: BinId) {
+        if let Some(Some(slot)) = self.triangles.remove(&bin_id) {
+            self.slots[slot] = None;
+            self.free_slots.push(slot);
+            extend_range(&mut self.dirty_slots, slot);
+
+            let triangle_coords = self.grid.triangle_coords(bin_id);
+            self.release_vertex(triangle_coords.0);
+            self.release_vertex(triangle_coords.1);
+            if let Some(mid_point_index) = self.release_vertex(triangle_coords.2) {
+                extend_range(&mut self.dirty_vertices, mid_point_index);
+            }
+        }
+    }
+
+    /// replaces a current triangle by its children, without its neighbours
+    fn split_triangle(&mut self, bin_id: BinId) {
+        if !self.contains(bin_id) {
+            return;
+        }
+
+        let (right_child_bin_id, left_child_bin_id) = get_triangle_children_bin_ids(bin_id);
+        self.remove_triangle(bin_id);
+        self.insert_triangle(left_child_bin_id);
+        self.insert_triangle(right_child_bin_id);
+    }
+
+    /// Splits a current triangle with its base neighbour, forcing the
+    /// splits that keeps the triangulation crack free. False when the
+    /// triangle is not current or is on the last level
+    pub fn split(&mut self, bin_id: BinId) -> bool {
+        if !self.contains(bin_id) || !self.can_split(bin_id) {
+            return false;
+        }
+
+        if let Some(base_bin_id) = get_triangle_base_neighbour_bin_id(bin_id) {
+            if !self.contains(base_bin_id) {
+                // the base neighbour is one level coarser, split its diamond first
+                if let Some(base_parent_bin_id) = get_triangle_parent_bin_id(base_bin_id) {
+                    self.split(base_parent_bin_id);
+                }
+            }
+            self.split_triangle(base_bin_id);
+        }
+        self.split_triangle(bin_id);
+
+        true
+    }
+
+    /// the triangle and its base neighbour when all their children are current
+    fn is_mergeable(&self, bin_id: BinId) -> bool {
+        let children_current = |bin_id| {
+            let (right_child_bin_id, left_child_bin_id) = get_triangle_children_bin_ids(bin_id);
+            self.contains(right_child_bin_id) && self.contains(left_child_bin_id)
+        };
+
+        children_current(bin_id) && match get_triangle_base_neighbour_bin_id(bin_id) {
+            Some(base_bin_id) => children_current(base_bin_id),
+            None => true,
+        }
+    }
+
+    /// Merges the children of a triangle and of its base neighbour back
+    /// into them. False unless all the children are current triangles
+    pub fn merge(&mut self, bin_id: BinId) -> bool {
+        if !self.is_mergeable(bin_id) {
+            return false;
+        }
+
+        let base_bin_id = get_triangle_base_neighbour_bin_id(bin_id);
+        for parent_bin_id in std::iter::once(bin_id).chain(base_bin_id) {
+            let (right_child_bin_id, left_child_bin_id) =
+                get_triangle_children_bin_ids(parent_bin_id);
+            self.remove_triangle(right_child_bin_id);
+            self.remove_triangle(left_child_bin_id);
+            self.insert_triangle(parent_bin_id);
+        }
+
+        true
+    }
+
+    fn triangle_error(&self, errors_vec: &[f32], bin_id: BinId) -> (TriangleU32, f32) {
+        let triangle_coords = self.grid.triangle_coords(bin_id);
+        let error = errors_vec[triangle_coords_errors_vec_index(
+            triangle_coords, self.grid.grid_size())];
+        (triangle_coords, error)
+    }
+
+    /// the triangles of the diamond of `bin_id`
+    fn diamond(bin_id: BinId) -> impl Iterator<Item = BinId> {
+        std::iter::once(bin_id).chain(get_triangle_base_neighbour_bin_id(bin_id))
+    }
+
+    /// Queues the triangles inserted since the last call, and the
+    /// diamonds they are split from. Queued triangles are checked again
+    /// when they come out of the queues, they may have changed since
+    fn queue_new_triangles(&mut self, errors_vec: &[f32]) {
+        let new_triangles = std::mem::replace(&mut self.new_triangles, Vec::new());
+
+        for bin_id in new_triangles {
+            if self.can_split(bin_id) && self.queued_splits.insert(bin_id) {
+                let (_, error) = self.triangle_error(errors_vec, bin_id);
+                self.split_queue.push(RtinSplitCandidate { error, bin_id });
+            }
+
+            let parent_bin_id = match get_triangle_parent_bin_id(bin_id) {
+                Some(parent_bin_id) => parent_bin_id,
+                None => continue,
+            };
+            if self.is_mergeable(parent_bin_id) && self.queued_merges.insert(parent_bin_id) {
+                let error = RtinRoam::diamond(parent_bin_id)
+                    .map(|diamond_bin_id| self.triangle_error(errors_vec, diamond_bin_id).1)
+                    .fold(0f32, f32::max);
+                self.merge_queue.push(Reverse(RtinSplitCandidate { error, bin_id: parent_bin_id }));
+            }
+        }
+    }
+
+    /// Merges the diamonds accurate enough without their children, lowest
+    /// error first, then splits the triangles not accurate enough, largest
+    /// error first. `check` tells whether a triangle is accurate, the same
+    /// as for the selection, and how far the camera can move before the
+    /// answer may change: the candidate is not checked again before.
+    ///
+    /// Errors at or below the first of `error_bounds` are always accurate,
+    /// errors above the second one never are: the queues are only gone
+    /// through between them
+    pub fn update<F>(&mut self, errors_vec: &[f32], check: &F, error_bounds: (f32, f32)) 
+        -> RtinRoamUpdate where F: Fn(TriangleU32, f32) -> (bool, f32) {
+
+        let (accurate_error, inaccurate_error) = error_bounds;
+        let mut update = RtinRoamUpdate::default();
+        self.queue_new_triangles(errors_vec);
+
+        let due_merges = pop_due_rechecks(&mut self.deferred_merges, self.travel);
+        self.merge_queue.extend(due_merges.into_iter().map(Reverse));
+        let due_splits = pop_due_rechecks(&mut self.deferred_splits, self.travel);
+        self.split_queue.extend(due_splits);
+
+        while let Some(Reverse(candidate)) = self.merge_queue.peek().copied() {
+            if candidate.error > inaccurate_error {
+                break;
+            }
+            self.merge_queue.pop();
+
+            if !self.is_mergeable(candidate.bin_id) {
+                self.queued_merges.remove(&candidate.bin_id);
+                continue;
+            }
+            update.checks += 1;
+            let checks: Vec<(bool, f32)> = RtinRoam::diamond(candidate.bin_id)
+                .map(|diamond_bin_id| {
+                    let (triangle_coords, triangle_error) = self.triangle_error(errors_vec, diamond_bin_id);
+                    check(triangle_coords, triangle_error)
+                })
+                .collect();
+            if checks.iter().any(|(accurate, _)| !accurate) {
+                // until every inaccurate triangle of the diamond may be accurate
+                let slack = checks.iter()
+                    .filter(|(accurate, _)| !accurate)
+                    .map(|(_, slack)| *slack)
+                    .fold(0f32, f32::max);
+                self.deferred_merges.push(Reverse(RoamRecheck { 
+                    travel: self.travel + slack as f64, candidate }));
+                continue;
+            }
+
+            self.queued_merges.remove(&candidate.bin_id);
+            self.merge(candidate.bin_id);
+            update.merges += 1;
+            // the merged triangles may now merge with their siblings
+            self.queue_new_triangles(errors_vec);
+        }
+
+        while let Some(candidate) = self.split_queue.peek().copied() {
+            if candidate.error <= accurate_error {
+                break;
+            }
+            self.split_queue.pop();
+
+            if !self.contains(candidate.bin_id) {
+                self.queued_splits.remove(&candidate.bin_id);
+                continue;
+            }
+            update.checks += 1;
+            let (triangle_coords, triangle_error) = self.triangle_error(errors_vec, candidate.bin_id);
+            let (accurate, slack) = check(triangle_coords, triangle_error);
+            if accurate {
+                self.deferred_splits.push(Reverse(RoamRecheck { 
+                    travel: self.travel + slack as f64, candidate }));
+                continue;
+            }
+
+            self.queued_splits.remove(&candidate.bin_id);
+            self.split(candidate.bin_id);
+            // counts the forced splits, one per pair of children
+            update.splits += self.new_triangles.len() / 2;
+            self.queue_new_triangles(errors_vec);
+        }
+
+        update
+    }
+
+    /// Checks every deferred candidate again at the next update, to call
+    /// when their accuracy changes in a way the updates can't tell, e.g.
+    /// with new load options
+    pub fn recheck_all(&mut self) {
+        self.split_queue.extend(self.deferred_splits.drain()
+            .map(|Reverse(recheck)| recheck.candidate));
+        self.merge_queue.extend(self.deferred_merges.drain()
+            .map(|Reverse(recheck)| Reverse(recheck.candidate)));
+    }
+
+    /// adds the camera move to the travel, or checks everything again
+    /// when the accuracy changes otherwise
+    fn set_accuracy(&mut self, accuracy: RoamAccuracy) {
+        match (self.accuracy, accuracy) {
+            (Some(RoamAccuracy::View(last_view)), RoamAccuracy::View(view)) 
+                    if last_view.has_same_projection(&view) =>
+                self.travel += (view.camera_position - last_view.camera_position).length() as f64,
+            (Some(last_accuracy), _) if last_accuracy == accuracy => {},
+            _ => self.recheck_all(),
+        }
+        self.accuracy = Some(accuracy);
+    }
+
+    pub fn update_for_threshold(&mut self, errors_vec: &[f32], error_threshold: f32) -> RtinRoamUpdate {
+        self.set_accuracy(RoamAccuracy::Threshold(error_threshold));
+        // accurate or not whatever the camera
+        self.update(errors_vec, 
+            &|_triangle, triangle_error| (triangle_error <= error_threshold, f32::INFINITY),
+            (error_threshold, error_threshold))
+    }
+
+    pub fn update_for_view(
+        &mut self,
+        errors_vec: &[f32],
+        view: &RtinView,
+        load_options: &TerrainImageLoadOptions) -> RtinRoamUpdate {
+        self.set_accuracy(RoamAccuracy::View(*view));
+        let error_bounds = view.error_bounds(self.grid.grid_size() - 1, load_options);
+        self.update(errors_vec, &|triangle, triangle_error| (
+            view.is_triangle_accurate(triangle, triangle_error, load_options),
+            view.accuracy_slack(triangle, triangle_error, load_options)
+        ), error_bounds)
+    }
+
+    /// the slots changed since the previous call, to copy into the meshes
+    pub fn take_dirty_slots(&mut self) -> Option<Range<usize>> {
+        self.dirty_slots.take()
+    }
+
+    /// the vertices changed since the previous call, to copy into the meshes
+    pub fn take_dirty_vertices(&mut self) -> Option<Range<usize>> {
+        self.dirty_vertices.take()
+    }
+
+    pub fn number_of_slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// pool vertices, free ones included
+    pub fn number_of_vertices(&self) -> usize {
+        self.vertices.len()
+    }
+
+    /// Indices of the triangles in `slots`, three per slot or six for
+    /// the wireframe lines. Free slots are degenerate triangles
+    pub fn slot_indices(&self, slots: Range<usize>, enable_wireframe: bool) -> Vec::<u32> {
+        let mut indices = Vec::<u32>::new();
+
+        for slot in &self.slots[slots] {
+            let corners = match slot {
+                Some(bin_id) => {
+                    let (a, b, c) = self.grid.triangle_coords(*bin_id);
+                    let vertex_index = |v: Vec2u32| self.vertex_indices[&self.vertex_id(v)] as u32;
+                    [vertex_index(a), vertex_index(b), vertex_index(c)]
+                },
+                None => [0; 3],
+            };
+
+            if enable_wireframe {
+                for j in &[0, 1, 1, 2, 2, 0] {
+                    indices.push(corners[*j]);
+                }
+            } else {
+                indices.extend(corners.iter());
+            }
+        }
+
+        indices
+    }
+
+    /// Copies the indices of `slots` into a mesh built from this
+    /// triangulation, growing its index buffer with the new slots
+    pub fn update_mesh_indices(&self, slots: Range<usize>, mesh: &mut Mesh, enable_wireframe: bool) {
+        let slot_len = if enable_wireframe { 6 } else { 3 };
+
+        if let Some(Indices::U32(indices)) = mesh.indices_mut() {
+            indices.resize(self.slots.len() * slot_len, 0);
+            let start = slots.start * slot_len;
+            let new_indices = self.slot_indices(slots, enable_wireframe);
+            indices[start..start + new_indices.len()].copy_from_slice(&new_indices);
+        }
+    }
+
+    /// Copies the attributes of `vertex_mesh`, made from the `vertices`
+    /// of the pool, into a mesh built from this triangulation, growing
+    /// its vertex buffers with the new vertices
+    pub fn update_mesh_vertices(&self, vertices: Range<usize>, vertex_mesh: &Mesh, mesh: &mut Mesh) {
+        fn copy_values<T: Copy + Default>(values: &mut Vec<T>, new_values: &[T], start: usize, len: usize) {
+            values.resize(len, T::default());
+            values[start..start + new_values.len()].copy_from_slice(new_values);
+        }
+
+        let len = self.vertices.len();
+        let attributes = [Mesh::ATTRIBUTE_POSITION, Mesh::ATTRIBUTE_NORMAL, Mesh::ATTRIBUTE_UV_0,
+            TerrainMaterial::ATTRIBUTE_COLOR, TerrainMaterial::ATTRIBUTE_MORPH_HEIGHT];
+
+        for attribute in &attributes {
+            match (mesh.attribute_mut(*attribute), vertex_mesh.attribute(*attribute)) {
+                (Some(VertexAttributeValues::Float(values)), Some(VertexAttributeValues::Float(new_values))) =>
+                    copy_values(values, new_values, vertices.start, len),
+                (Some(VertexAttributeValues::Float2(values)), Some(VertexAttributeValues::Float2(new_values))) =>
+                    copy_values(values, new_values, vertices.start, len),
+                (Some(VertexAttributeValues::Float3(values)), Some(VertexAttributeValues::Float3(new_values))) =>
+                    copy_values(values, new_values, vertices.start, len),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Mesh data of the `vertices` of the pool, without indices. Free
+/// vertices are copies of the first grid point. Vertices carry no error
+/// nor level, normals come from the heightmap
+pub fn rtin_roam_vertex_data(rtin_terrain: &RtinTerrain, roam: &RtinRoam, vertices: Range<usize>) 
+    -> TerrainMeshData {
+    let heightmap = rtin_terrain.heightmap();
+    let roam_vertices = &roam.vertices[vertices];
+
+    let grid_points: Vec::<Vec2u32> = roam_vertices.iter()
+        .map(|vertex| vertex.map_or(Vec2u32::new(0, 0), |vertex| vertex.point))
+        .collect();
+
+    let vertices: Vec::<Vec3> = grid_points.iter()
+        .map(|point| Vec3::new(point[0] as f32,
+            sample_heightmap_height_corner_mean(heightmap, *point), point[1] as f32))
+        .collect();
+    let normals = grid_points.iter()
+        .map(|point| heightmap_normal_at(heightmap, point[0], point[1]))
+        .collect();
+    let uvs = vertices.iter()
+        .map(|vertex| heightmap_pixel_uv(heightmap, vertex.x, vertex.z))
+        .collect();
+    let curvatures = grid_points.iter()
+        .map(|point| heightmap_laplacian_at(heightmap, point[0], point[1]))
+        .collect();
+    let morph_heights = roam_vertices.iter().zip(&vertices)
+        .map(|(roam_vertex, vertex)| roam_vertex
+            .and_then(|roam_vertex| roam_vertex.diamond_child)
+            .and_then(|bin_id| rtin_diamond_morph_height(heightmap, roam.grid(), roam.extent,
+                bin_id, |bin_id| roam.contains(bin_id)))
+            .map_or(vertex.y, |(_, morph_height)| morph_height))
+        .collect();
+
+    TerrainMeshData {
+        indices: Vec::new(),
+        errors: vec![0f32; vertices.len()],
+        levels: vec![0; vertices.len()],
+        vertices,
+        normals,
+        uvs,
+        curvatures,
+        shades: Vec::new(),
+        morph_heights,
+    }
+}
+
+/// mesh data of the whole pool, with the indices of every slot
+pub fn rtin_roam_mesh_data(rtin_terrain: &RtinTerrain, roam: &RtinRoam) -> TerrainMeshData {
+    TerrainMeshData {
+        indices: roam.slot_indices(0..roam.number_of_slots(), false),
+        ..rtin_roam_vertex_data(rtin_terrain, roam, 0..roam.number_of_vertices())
+    }
+}
+
+/// Marks a terrain refined by a persistent `RtinRoam` triangulation,
+/// for the camera when it has a screen space error and for its error
+/// threshold otherwise. It is refined again when the camera moves by
+/// the remesh distance or the `Terrain` changes. Its meshes are built
+/// again when the `Terrain` changes, else only the changed indices and
+/// vertices are copied into them. Skirts are not supported
+#[derive(Default)]
+pub struct RoamTerrain {
+    roam: Option<RtinRoam>,
+    /// the heightmap `roam` triangulates
+    heightmap: Handle<Heightmap>,
+    /// the view `roam` was last refined for
+    view: Option<TerrainView>,
+}
+
+/// refine the ROAM terrains, starting over when their heightmap changes
+pub fn rtin_roam_terrain_system(
+    commands: &mut Commands,
+    windows: Res<Windows>,
+    mut heightmap_event_reader: Local<EventReader<AssetEvent<Heightmap>>>,
+    heightmap_events: Res<Events<AssetEvent<Heightmap>>>,
+    heightmaps: Res<Assets<Heightmap>>,
+    mut rtin_terrains: ResMut<RtinTerrainCache>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    camera_query: Query<(&GlobalTransform, &PerspectiveProjection)>,
+    changed_terrains_query: Query<Entity, (Changed<Terrain>, With<RoamTerrain>)>,
+    mut terrain_query: Query<(Entity, &Terrain, &GlobalTransform, &mut RoamTerrain,
+        Option<&TerrainMeshes>, &mut Handle<Mesh>), Without<TerrainTile>>,
+) {
+    for event in heightmap_event_reader.iter(&heightmap_events) {
+        let handle = match event {
+            AssetEvent::Created { handle } |
+            AssetEvent::Modified { handle } |
+            AssetEvent::Removed { handle } => handle,
+        };
+
+        for (_, terrain, _, mut roam_terrain, ..) in terrain_query.iter_mut() {
+            if terrain.heightmap == *handle {
+                // the event may not have reached the cache yet
+                rtin_terrains.invalidate(handle);
+                roam_terrain.roam = None;
+            }
+        }
+    }
+
+    let changed_terrains: HashSet<Entity> = changed_terrains_query.iter().collect();
+
+    let viewport_height = windows.get_primary().map(|window| window.height() as f32);
+    let camera = camera_query.iter().next();
+
+    for (entity, terrain, terrain_transform, mut roam_terrain, terrain_meshes, mut mesh)
+            in terrain_query.iter_mut() {
+
+        let view = match (&terrain.screen_space_error, viewport_height, camera) {
+            (None, ..) => None,
+            (Some(screen_space_error), Some(viewport_height), Some((camera_transform, projection))) =>
+                Some(TerrainView {
+                    view: RtinView {
+                        camera_position: terrain_transform.compute_matrix().inverse()
+                            .transform_point3(camera_transform.translation),
+                        fov: projection.fov,
+                        viewport_height,
+                        max_pixel_error: screen_space_error.max_pixel_error,
+                    },
+                    camera_translation: camera_transform.translation,
+                }),
+            // not seen by a camera yet
+            (Some(_), ..) => continue,
+        };
+
+        let roam_terrain = &mut *roam_terrain;
+        let new_roam = roam_terrain.roam.is_none() || roam_terrain.heightmap != terrain.heightmap;
+        let terrain_changed = changed_terrains.contains(&entity);
+
+        // the same as the RTIN view system
+        let view_outdated = match (&view, &roam_terrain.view, &terrain.screen_space_error) {
+            (Some(view), Some(last_view), Some(screen_space_error)) => {
+                let camera_move = (last_view.camera_translation - view.camera_translation).length();
+                camera_move >= screen_space_error.remesh_distance || 
+                    !last_view.view.has_same_projection(&view.view)
+            },
+            (None, None, _) => false,
+            _ => true,
+        };
+        if !new_roam && !terrain_changed && !view_outdated && terrain_meshes.is_some() {
+            continue;
+        }
+
+        // not loaded yet
+        let heightmap = match heightmaps.get(&terrain.heightmap) {
+            Some(heightmap) => heightmap,
+            None => continue,
+        };
+
+        let rtin_terrain = match rtin_terrains.get_or_insert(&terrain.heightmap, heightmap) {
+            Ok(rtin_terrain) => rtin_terrain,
+            Err(err) => {
+                error!("cannot mesh terrain heightmap: {}", err);
+                continue;
+            }
+        };
+
+        if new_roam {
+            roam_terrain.roam = Some(
+                RtinRoam::new(rtin_terrain.heightmap(), rtin_terrain.grid().clone()));
+            roam_terrain.heightmap = terrain.heightmap.clone_weak();
+        }
+        let roam = roam_terrain.roam.as_mut().unwrap();
+
+        if terrain_changed {
+            // the load options may have changed
+            roam.recheck_all();
+        }
+        match &view {
+            Some(view) => roam.update_for_view(
+                rtin_terrain.errors(), &view.view, &terrain.load_options),
+            None => roam.update_for_threshold(
+                rtin_terrain.errors(), terrain.error_threshold),
+        };
+        roam_terrain.view = view;
+
+        let dirty_slots = roam.take_dirty_slots();
+        let dirty_vertices = roam.take_dirty_vertices();
+
+        // skirt indices would come after the slots
+        let load_options = TerrainImageLoadOptions {
+            skirt: None,
+            ..terrain.load_options.clone()
+        };
+
+        match terrain_meshes {
+            Some(terrain_meshes) if !new_roam && !terrain_changed => {
+                // getting the meshes mutably uploads them again
+                if dirty_slots.is_none() && dirty_vertices.is_none() {
+                    continue;
+                }
+
+                let vertex_mesh = dirty_vertices.clone().map(|dirty_vertices| 
+                    rtin_make_terrain_meshes_from_data(rtin_terrain.heightmap(),
+                        rtin_roam_vertex_data(rtin_terrain, roam, dirty_vertices), &load_options).0);
+
+                for (handle, enable_wireframe) in &[
+                        (&terrain_meshes.shaded, false), (&terrain_meshes.wireframe, true)] {
+                    let terrain_mesh = match meshes.get_mut(*handle) {
+                        Some(terrain_mesh) => terrain_mesh,
+                        None => continue,
+                    };
+                    if let (Some(dirty_vertices), Some(vertex_mesh)) = (&dirty_vertices, &vertex_mesh) {
+                        roam.update_mesh_vertices(dirty_vertices.clone(), vertex_mesh, terrain_mesh);
+                    }
+                    if let Some(dirty_slots) = &dirty_slots {
+                        roam.update_mesh_indices(dirty_slots.clone(), terrain_mesh, *enable_wireframe);
+                    }
+                }
+            },
+            _ => {
+                let new_meshes = rtin_make_terrain_meshes_from_data(
+                    rtin_terrain.heightmap(), rtin_roam_mesh_data(rtin_terrain, roam),
+                    &load_options);
+                let new_terrain_meshes = rtin_store_terrain_meshes(
+                    new_meshes, &mut meshes, terrain_meshes);
+                if terrain_meshes.is_none() {
+                    *mesh = new_terrain_meshes.shaded.clone();
+                    commands.insert_one(entity, new_terrain_meshes);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{heightmap::sine_height_source, rtin::bin_id_to_level, terrain_rtin::rtin_select_triangles_for_heightmap};
+
+    fn sine_rtin_terrain(width: u32, height: u32) -> RtinTerrain {
+        RtinTerrain::new(Heightmap::from_height_source(&sine_height_source(width, height))).unwrap()
+    }
+
+    fn selection(rtin_terrain: &RtinTerrain, error_threshold: f32) -> Vec::<BinId> {
+        let mut triangle_bin_ids = rtin_select_triangles_for_heightmap(
+            rtin_terrain.heightmap(), rtin_terrain.grid(), &rtin_terrain.errors().to_vec(),
+            error_threshold);
+        triangle_bin_ids.sort();
+        triangle_bin_ids
+    }
+
+    /// the triangles overlapping the heightmap, as in the index buffer
+    fn slot_bin_ids(roam: &RtinRoam) -> Vec::<BinId> {
+        let mut slot_bin_ids: Vec::<BinId> = roam.slots.iter().filter_map(|slot| *slot).collect();
+        slot_bin_ids.sort();
+        slot_bin_ids
+    }
+
+    #[test]
+    fn test_roam_matches_selection() {
+        let rtin_terrain = sine_rtin_terrain(33, 33);
+        let mut roam = RtinRoam::new(rtin_terrain.heightmap(), rtin_terrain.grid().clone());
+
+        for error_threshold in &[0.5f32, 0.05, 0.0, 0.1, 0.3, 1.0] {
+            roam.update_for_threshold(rtin_terrain.errors(), *error_threshold);
+            assert_eq!(slot_bin_ids(&roam), selection(&rtin_terrain, *error_threshold));
+            assert_eq!(roam.update_for_threshold(rtin_terrain.errors(), *error_threshold),
+                RtinRoamUpdate::default());
+        }
+
+        // clipped triangles have no slot
+        let clipped_terrain = sine_rtin_terrain(20, 27);
+        let mut clipped_roam = RtinRoam::new(
+            clipped_terrain.heightmap(), clipped_terrain.grid().clone());
+        clipped_roam.update_for_threshold(clipped_terrain.errors(), 0.05);
+        assert_eq!(slot_bin_ids(&clipped_roam), selection(&clipped_terrain, 0.05));
+    }
+
+    #[test]
+    fn test_roam_matches_view_selection() {
+        let rtin_terrain = sine_rtin_terrain(33, 33);
+        let mut roam = RtinRoam::new(rtin_terrain.heightmap(), rtin_terrain.grid().clone());
+        let load_options = TerrainImageLoadOptions {
+            max_image_height: 10.0,
+            pixel_side_length: 1.0,
+            ..Default::default()
+        };
+
+        for camera_position in &[Vec3::new(16.0, 12.0, 16.0), Vec3::new(0.0, 12.0, 0.0),
+                Vec3::new(16.0, 40.0, 500.0), Vec3::new(30.0, 3.0, 2.0)] {
+            let view = RtinView {
+                camera_position: *camera_position,
+                fov: 1.0,
+                viewport_height: 720.0,
+                max_pixel_error: 2.0,
+            };
+            roam.update_for_view(rtin_terrain.errors(), &view, &load_options);
+
+            let mut expected = crate::terrain_rtin::rtin_select_triangles_for_view(
+                rtin_terrain.heightmap(), rtin_terrain.grid(), &rtin_terrain.errors().to_vec(),
+                &view, &load_options);
+            expected.sort();
+            assert_eq!(slot_bin_ids(&roam), expected);
+        }
+    }
+
+    #[test]
+    fn test_roam_small_camera_moves_check_few_candidates() {
+        let rtin_terrain = sine_rtin_terrain(65, 65);
+        let mut roam = RtinRoam::new(rtin_terrain.heightmap(), rtin_terrain.grid().clone());
+        let load_options = TerrainImageLoadOptions {
+            max_image_height: 10.0,
+            pixel_side_length: 1.0,
+            ..Default::default()
+        };
+        let view_at = |x: f32| RtinView {
+            camera_position: Vec3::new(x, 12.0, 32.0),
+            fov: 1.0,
+            viewport_height: 720.0,
+            max_pixel_error: 2.0,
+        };
+
+        let first_update = roam.update_for_view(rtin_terrain.errors(), &view_at(8.0), &load_options);
+        // checks the diamonds split by the first update once
+        let settle_update = roam.update_for_view(rtin_terrain.errors(), &view_at(8.0), &load_options);
+        assert_eq!(settle_update.splits + settle_update.merges, 0);
+
+        for step in 1..=20 {
+            let view = view_at(8.0 + step as f32 * 0.1);
+            let update = roam.update_for_view(rtin_terrain.errors(), &view, &load_options);
+            // only the candidates near the accuracy limit are checked again
+            assert!(update.checks * 20 < first_update.checks);
+
+            let mut expected = crate::terrain_rtin::rtin_select_triangles_for_view(
+                rtin_terrain.heightmap(), rtin_terrain.grid(), &rtin_terrain.errors().to_vec(),
+                &view, &load_options);
+            expected.sort();
+            assert_eq!(slot_bin_ids(&roam), expected);
+        }
+    }
+
+    #[test]
+    fn test_roam_forced_splits() {
+        let rtin_terrain = sine_rtin_terrain(17, 17);
+        let mut roam = RtinRoam::new(rtin_terrain.heightmap(), rtin_terrain.grid().clone());
+
+        let deep_bin_id: BinId = 0b1_0110_1010;
+        assert_eq!(bin_id_to_level(deep_bin_id), 7);
+
+        // splitting down to a deep triangle forces the splits of coarser neighbours
+        let mut bin_id = deep_bin_id;
+        let mut ancestors = vec![];
+        while let Some(parent_bin_id) = get_triangle_parent_bin_id(bin_id) {
+            ancestors.push(parent_bin_id);
+            bin_id = parent_bin_id;
+        }
+        for ancestor_bin_id in ancestors.iter().rev() {
+            assert!(roam.split(*ancestor_bin_id));
+        }
+        assert!(roam.contains(deep_bin_id));
+        assert!(roam.split(deep_bin_id));
+        assert!(!roam.split(deep_bin_id));
+        assert!(roam.triangles.len() > 2 + ancestors.len() + 1);
+
+        // every current triangle has its base neighbour or the parent of it
+        for bin_id in roam.triangle_bin_ids() {
+            if let Some(base_bin_id) = get_triangle_base_neighbour_bin_id(bin_id) {
+                let base_parent_bin_id = get_triangle_parent_bin_id(base_bin_id);
+                assert!(roam.contains(base_bin_id) ||
+                    base_parent_bin_id.map_or(false, |parent| roam.contains(parent)));
+            }
+        }
+
+        // and merging back down to the two level 0 triangles
+        roam.update(rtin_terrain.errors(), &|_, _| (true, f32::INFINITY), (f32::INFINITY, f32::INFINITY));
+        assert_eq!(roam.triangle_bin_ids(), vec![0b10, 0b11]);
+    }
+
+    #[test]
+    fn test_roam_dirty_slots() {
+        let rtin_terrain = sine_rtin_terrain(17, 17);
+        let mut roam = RtinRoam::new(rtin_terrain.heightmap(), rtin_terrain.grid().clone());
+        roam.update_for_threshold(rtin_terrain.errors(), 0.2);
+
+        let mut mesh_data = rtin_roam_mesh_data(&rtin_terrain, &roam);
+        assert!(mesh_data.vertices.len() < 17 * 17);
+        assert_eq!(roam.take_dirty_slots(), Some(0..roam.number_of_slots()));
+        assert_eq!(roam.take_dirty_slots(), None);
+        assert_eq!(roam.take_dirty_vertices(), Some(0..roam.number_of_vertices()));
+
+        for error_threshold in &[0.05, 0.5, 0.0] {
+            roam.update_for_threshold(rtin_terrain.errors(), *error_threshold);
+
+            let dirty_slots = roam.take_dirty_slots().unwrap();
+            mesh_data.indices.resize(roam.number_of_slots() * 3, 0);
+            let new_indices = roam.slot_indices(dirty_slots.clone(), false);
+            mesh_data.indices[dirty_slots.start * 3..dirty_slots.end * 3]
+                .copy_from_slice(&new_indices);
+
+            let dirty_vertices = roam.take_dirty_vertices().unwrap();
+            let vertex_data = rtin_roam_vertex_data(&rtin_terrain, &roam, dirty_vertices.clone());
+            mesh_data.vertices.resize(roam.number_of_vertices(), Vec3::zero());
+            mesh_data.vertices[dirty_vertices.clone()].copy_from_slice(&vertex_data.vertices);
+            mesh_data.morph_heights.resize(roam.number_of_vertices(), 0f32);
+            mesh_data.morph_heights[dirty_vertices].copy_from_slice(&vertex_data.morph_heights);
+
+            let rebuilt = rtin_roam_mesh_data(&rtin_terrain, &roam);
+            assert_eq!(mesh_data.indices, rebuilt.indices);
+
+            // the free vertices are not used by any slot
+            let used_vertices: HashSet<u32> = rebuilt.indices.iter().copied().collect();
+            for index in used_vertices {
+                let index = index as usize;
+                assert_eq!(mesh_data.vertices[index], rebuilt.vertices[index]);
+                assert_eq!(mesh_data.morph_heights[index], rebuilt.morph_heights[index]);
+            }
+
+            // the pool holds the vertices of the triangles overlapping the heightmap
+            let selection_mesh_data = rtin_terrain.mesh_data_for_threshold(*error_threshold);
+            assert_eq!(roam.vertex_indices.len(), selection_mesh_data.vertices.len());
+            let mut morph_heights: Vec::<(Vec3, f32)> = roam.vertex_indices.values()
+                .map(|index| (rebuilt.vertices[*index], rebuilt.morph_heights[*index]))
+                .collect();
+            let mut expected_morph_heights: Vec::<(Vec3, f32)> = selection_mesh_data.vertices.iter()
+                .copied()
+                .zip(selection_mesh_data.morph_heights)
+                .collect();
+            let by_position = |a: &(Vec3, f32), b: &(Vec3, f32)| 
+                (a.0.x, a.0.z).partial_cmp(&(b.0.x, b.0.z)).unwrap();
+            morph_heights.sort_by(by_position);
+            expected_morph_heights.sort_by(by_position);
+            assert_eq!(morph_heights, expected_morph_heights);
+        }
+    }
+}
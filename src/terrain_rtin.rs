@@ -1,43 +1,93 @@
-use crate::{terrain_common::TerrainImageLoadOptions, terrain_material::TerrainMaterial};
+use crate::{
+    heightmap::{HeightChannel, HeightEncoding, HeightSource, Heightmap},
+    hillshade::hillshade_at,
+    normals::{NormalSource, heightmap_laplacian_at, heightmap_normal_at, scale_grid_normal, smooth_vertex_normals},
+    rtin::{
+        BinId, RtinGrid, TriangleExtentOverlap, TriangleU32, Vec2u32,
+        bin_id_to_level, get_index_level_start, get_triangle_base_neighbour_bin_id,
+        get_triangle_children_bin_ids, get_triangle_children_indices, get_triangle_parent_bin_id,
+        index_to_bin_id, pixel_coords_for_triangle_mid_point, triangle_extent_overlap,
+    },
+    skirt::mesh_skirt,
+    terrain_common::{Terrain, TerrainError, TerrainImageLoadOptions, TerrainMeshes, TerrainView},
+    terrain_material::TerrainMaterial,
+    terrain_roam::RoamTerrain,
+    terrain_tiles::TerrainTile,
+    vertex_colorizer::{TerrainVertex, VertexColorizer},
+};
 // use Srgb::into_raw;
-use image::{ImageBuffer, Luma};
 extern crate nalgebra as na;
 use bevy_render::{
     pipeline::PrimitiveTopology,
     mesh::{Mesh, VertexAttributeValues, Indices},
 };
 use na::Scalar;
-use std::{collections::HashMap, vec::Vec};
+use std::{collections::{HashMap, HashSet}, sync::Arc, vec::Vec};
 use bevy::prelude::*;
-use anyhow::Result;
-use palette::{FromColor, Gradient, Hsv, LinSrgb, Srgb};
+use bevy::{render::camera::PerspectiveProjection, window::Windows};
 
 type ErrorsVec = Vec::<f32>;
 
-use crate::rtin::{BinId, TriangleU32, Vec2u32, get_index_level_start, get_triangle_children_bin_ids, get_triangle_children_indices, get_triangle_coords, index_to_bin_id, pixel_coords_for_triangle_mid_point};
-
-type HeightMapU16 = ImageBuffer<Luma<u16>, Vec::<u16>>;
-
-#[derive(Default)]
-pub struct RtinParams {
-    pub error_threshold: f32, 
-    pub load_options: TerrainImageLoadOptions
-}
-
 pub type Trianglef32 = (Vec3, Vec3, Vec3);
 
+/// Largest RTIN grid side. The meshing stores bin ids in a `u32`
+/// `BinId`, which suffices up to 2^15: the leaves of this grid are at
+/// level 30 and need the 32 bits. Larger grids would need the pipeline
+/// to use the `u64` bin id math of `rtin`
+pub const RTIN_MAX_GRID_SIDE: u32 = 1 << 15;
+
 
 /// https://codegolf.stackexchange.com/questions/44680/showcase-of-languages
 pub fn is_power_of_2(x: u32) -> bool {
     ( x & !( x & (x-1) ) ) > 0
 }
 
-pub fn assert_valid_rtin_heightmap(heightmap: &HeightMapU16) {
-    assert_eq!(heightmap.width(), heightmap.height());
-    assert!(is_power_of_2(heightmap.width()));
+pub fn assert_valid_rtin_heightmap<H: HeightSource + ?Sized>(heightmap: &H) {
+    assert!(heightmap.width() > 0);
+    assert!(heightmap.height() > 0);
+}
+
+pub fn validate_rtin_heightmap<H: HeightSource + ?Sized>(heightmap: &H) -> Result<(), TerrainError> {
+    if heightmap.width() == 0 || heightmap.height() == 0 {
+        return Err(TerrainError::EmptyImage);
+    }
+
+    if rtin_grid_side(heightmap) > RTIN_MAX_GRID_SIDE {
+        return Err(TerrainError::InvalidDimensions {
+            width: heightmap.width(),
+            height: heightmap.height()
+        });
+    }
+
+    Ok(())
+}
+
+/// side of the power of two RTIN grid covering the heightmap.
+///
+/// Heightmaps of side 2^n + 1 map exactly onto a grid of side 2^n,
+/// any other size is padded up to the next power of two
+pub fn rtin_grid_side<H: HeightSource + ?Sized>(heightmap: &H) -> u32 {
+    let max_side = heightmap.width().max(heightmap.height()).max(2);
+    (max_side - 1).next_power_of_two()
+}
+
+/// the triangle coordinate tables of the RTIN grid covering the heightmap
+pub fn rtin_grid_for_heightmap<H: HeightSource + ?Sized>(heightmap: &H) -> RtinGrid {
+    RtinGrid::new(rtin_grid_side(heightmap) + 1)
 }
 
-pub fn assert_coordinate_is_within_heightmap(heightmap: &HeightMapU16, coord: Vec2u32) {
+/// the part of the RTIN grid actually covered by the heightmap, up to
+/// the last pixel, triangles outside of it are clipped away.
+///
+/// Heightmaps one pixel wide or high keep one grid cell, repeating their pixels
+pub fn rtin_grid_extent<H: HeightSource + ?Sized>(heightmap: &H) -> Vec2u32 {
+    let side = rtin_grid_side(heightmap);
+    Vec2u32::new(
+        (heightmap.width() - 1).max(1).min(side),
+        (heightmap.height() - 1).max(1).min(side))
+}
+
+pub fn assert_coordinate_is_within_heightmap<H: HeightSource + ?Sized>(heightmap: &H, coord: Vec2u32) {
     assert!(coord[0] < heightmap.width());
     assert!(coord[1] < heightmap.height());
 }
@@ -54,6 +104,23 @@ pub fn triangleu32_to_trianglef32(triangle: TriangleU32) -> Trianglef32 {
     )
 }
 
+/// approximation error of a triangle, the smallest triangles have 
+/// no midpoint on the grid and match the heightmap exactly
+pub fn rtin_triangle_error(
+    triangle: TriangleU32, 
+    errors_vec: &ErrorsVec, 
+    grid_size: u32) -> f32 {
+
+    let hypotenuse_sum = triangle.0 + triangle.1;
+    let has_grid_midpoint = hypotenuse_sum[0] % 2 == 0 && hypotenuse_sum[1] % 2 == 0;
+
+    if has_grid_midpoint {
+        errors_vec[triangle_coords_errors_vec_index(triangle, grid_size)]
+    } else {
+        0f32
+    }
+}
+
 pub fn triangle_errors_vec_index(bin_id: BinId, grid_size: u32) -> usize {
     let triangle_midpoint = pixel_coords_for_triangle_mid_point(
         bin_id, grid_size);
@@ -64,61 +131,624 @@ pub fn triangle_errors_vec_index(bin_id: BinId, grid_size: u32) -> usize {
     midpoint_error_vec_index as usize
 }
 
-pub fn rtin_select_triangles_for_heightmap_process_triangle(
-    heightmap: &HeightMapU16, 
+/// same as `triangle_errors_vec_index`, from the triangle corners
+pub fn triangle_coords_errors_vec_index(triangle: TriangleU32, grid_size: u32) -> usize {
+    let triangle_midpoint = (triangle.0 + triangle.1) / 2;
+    (triangle_midpoint[1] * grid_size + triangle_midpoint[0]) as usize
+}
+
+pub fn rtin_select_triangles_for_heightmap_process_triangle<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
     errors_vec: &Vec::<f32>,
     triangles: &mut Vec::<BinId>, 
     triangle_index: u32, 
     error_threshold: f32)  {
-    
-    let grid_size = heightmap.width() + 1;
 
-    let triangle_bin_id = index_to_bin_id(triangle_index);
+    rtin_select_triangles_process_triangle(
+        heightmap, grid, errors_vec, triangles, triangle_index, 
+        &|_triangle, triangle_error| triangle_error <= error_threshold);
+}
 
-    let (right_child_index, left_child_index) = 
-        get_triangle_children_indices(triangle_bin_id);
+/// what the selection does with a triangle
+enum RtinSelectionStep {
+    Select(BinId),
+    /// outside of the heightmap
+    Skip,
+    /// recurse into the left then the right child, by index
+    Split(u32, u32),
+}
 
-    let side = heightmap.width();
-    let number_of_last_level_triangles = side*side*2;
-    let number_of_triangles = side * side * 2 - 2 + number_of_last_level_triangles;
+/// `is_accurate` tells whether a triangle of the given error needs no split
+fn rtin_select_triangle_step<H, F>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &Vec::<f32>,
+    triangle_index: u32, 
+    is_accurate: &F) -> RtinSelectionStep
+    where H: HeightSource + ?Sized, F: Fn(TriangleU32, f32) -> bool {
+    
+    let side = rtin_grid_side(heightmap);
+    let grid_size = side + 1;
+    debug_assert_eq!(grid.grid_size(), grid_size);
 
-    let has_children = right_child_index < number_of_triangles;
+    let triangle_bin_id = index_to_bin_id(triangle_index);
 
-    let leaf_triangle = !has_children;
+    let leaf_triangle = !grid.has_children(triangle_bin_id);
 
-    let this_triangle_errors_vec_index = triangle_errors_vec_index(
-        triangle_bin_id, grid_size);
+    let triangle_coords = grid.triangle_coords(triangle_bin_id);
+    let this_triangle_errors_vec_index = triangle_coords_errors_vec_index(
+        triangle_coords, grid_size);
     let this_triangle_error = errors_vec[this_triangle_errors_vec_index];
-    let error_within_threshold = this_triangle_error <= error_threshold;
+    let error_within_threshold = is_accurate(triangle_coords, this_triangle_error);
 
 
     if error_within_threshold || leaf_triangle {
-        triangles.push(triangle_bin_id);
+        let overlap = triangle_extent_overlap(
+            triangle_coords, rtin_grid_extent(heightmap));
+        if overlap != TriangleExtentOverlap::Outside {
+            RtinSelectionStep::Select(triangle_bin_id)
+        } else {
+            RtinSelectionStep::Skip
+        }
     } else {
-        rtin_select_triangles_for_heightmap_process_triangle(
-            heightmap, errors_vec, triangles, left_child_index, error_threshold);
-        rtin_select_triangles_for_heightmap_process_triangle(
-            heightmap, errors_vec, triangles, right_child_index, error_threshold);
+        let (right_child_index, left_child_index) = 
+            get_triangle_children_indices(triangle_bin_id);
+        RtinSelectionStep::Split(left_child_index, right_child_index)
+    }
+}
+
+/// selects the triangle or recurses into its children
+fn rtin_select_triangles_process_triangle<H, F>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &Vec::<f32>,
+    triangles: &mut Vec::<BinId>, 
+    triangle_index: u32, 
+    is_accurate: &F) 
+    where H: HeightSource + ?Sized, F: Fn(TriangleU32, f32) -> bool {
+
+    match rtin_select_triangle_step(heightmap, grid, errors_vec, triangle_index, is_accurate) {
+        RtinSelectionStep::Select(triangle_bin_id) => triangles.push(triangle_bin_id),
+        RtinSelectionStep::Skip => {}
+        RtinSelectionStep::Split(left_child_index, right_child_index) => {
+            rtin_select_triangles_process_triangle(
+                heightmap, grid, errors_vec, triangles, left_child_index, is_accurate);
+            rtin_select_triangles_process_triangle(
+                heightmap, grid, errors_vec, triangles, right_child_index, is_accurate);
+        }
+    }
+}
+
+/// triangles deeper than this are selected sequentially by their task
+#[cfg(feature = "parallel")]
+const RTIN_PARALLEL_MAX_LEVEL: u32 = 10;
+
+/// same as `rtin_select_triangles_process_triangle`, the two children
+/// of the first levels are selected concurrently
+#[cfg(feature = "parallel")]
+fn rtin_select_triangles_process_triangle_par<H, F>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &Vec::<f32>,
+    triangle_index: u32, 
+    is_accurate: &F) -> Vec::<BinId>
+    where H: HeightSource + Sync + ?Sized, F: Fn(TriangleU32, f32) -> bool + Sync {
+
+    let mut triangles = Vec::<BinId>::new();
+
+    if bin_id_to_level(index_to_bin_id(triangle_index)) >= RTIN_PARALLEL_MAX_LEVEL {
+        rtin_select_triangles_process_triangle(
+            heightmap, grid, errors_vec, &mut triangles, triangle_index, is_accurate);
+        return triangles;
+    }
+
+    match rtin_select_triangle_step(heightmap, grid, errors_vec, triangle_index, is_accurate) {
+        RtinSelectionStep::Select(triangle_bin_id) => triangles.push(triangle_bin_id),
+        RtinSelectionStep::Skip => {}
+        RtinSelectionStep::Split(left_child_index, right_child_index) => {
+            let (left_triangles, right_triangles) = rayon::join(
+                || rtin_select_triangles_process_triangle_par(
+                    heightmap, grid, errors_vec, left_child_index, is_accurate),
+                || rtin_select_triangles_process_triangle_par(
+                    heightmap, grid, errors_vec, right_child_index, is_accurate));
+            triangles = left_triangles;
+            triangles.extend(right_triangles);
+        }
     }
+
+    triangles
 }
 
+/// selects the triangles of both roots concurrently, in the same order
+/// as the sequential selection
+#[cfg(feature = "parallel")]
+fn rtin_select_triangles_par<H, F>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &Vec::<f32>,
+    is_accurate: &F) -> Vec::<BinId>
+    where H: HeightSource + Sync + ?Sized, F: Fn(TriangleU32, f32) -> bool + Sync {
+
+    let (mut triangles, second_root_triangles) = rayon::join(
+        || rtin_select_triangles_process_triangle_par(heightmap, grid, errors_vec, 0, is_accurate),
+        || rtin_select_triangles_process_triangle_par(heightmap, grid, errors_vec, 1, is_accurate));
+    triangles.extend(second_root_triangles);
+
+    triangles
+}
+
+/// open a heightmap image, see `Heightmap::open`, and mesh it
 pub fn rtin_load_terrain(
     filename: &str,
-    rtin_params: &RtinParams) -> (Mesh, Mesh) {
+    encoding: HeightEncoding,
+    channel: HeightChannel,
+    error_threshold: f32,
+    load_options: &TerrainImageLoadOptions) -> Result<(Mesh, Mesh), TerrainError> {
+
+    let terrain_heightmap = Heightmap::open(filename, encoding, channel)?;
+
+    rtin_make_terrain_meshes(&terrain_heightmap, error_threshold, load_options)
+}
+
+/// build the shaded and wireframe meshes of an already loaded heightmap
+pub fn rtin_make_terrain_meshes<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    error_threshold: f32,
+    load_options: &TerrainImageLoadOptions) -> Result<(Mesh, Mesh), TerrainError> {
+
+    validate_rtin_heightmap(heightmap)?;
 
-    let terrain_image = image::open(filename).unwrap();
-    let terrain_heightmap = terrain_image.as_luma16().unwrap();
     let terrain_mesh_data = rtin_build_terrain_from_heightmap(
-        terrain_heightmap, rtin_params.error_threshold);
+        heightmap, error_threshold);
+
+    Ok(rtin_make_terrain_meshes_from_data(heightmap, terrain_mesh_data, load_options))
+}
+
+/// build the shaded and wireframe meshes of an already loaded heightmap,
+/// refined for the view of a camera
+pub fn rtin_make_terrain_meshes_for_view<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    view: &RtinView,
+    load_options: &TerrainImageLoadOptions) -> Result<(Mesh, Mesh), TerrainError> {
+
+    validate_rtin_heightmap(heightmap)?;
+
+    let terrain_mesh_data = rtin_build_terrain_from_heightmap_for_view(
+        heightmap, view, load_options);
+
+    Ok(rtin_make_terrain_meshes_from_data(heightmap, terrain_mesh_data, load_options))
+}
+
+/// build the shaded and wireframe meshes of an already loaded heightmap,
+/// as accurate as the budget allows
+pub fn rtin_make_terrain_meshes_for_budget<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    budget: RtinBudget,
+    load_options: &TerrainImageLoadOptions) -> Result<(Mesh, Mesh), TerrainError> {
+
+    validate_rtin_heightmap(heightmap)?;
+
+    let terrain_mesh_data = rtin_build_terrain_from_heightmap_for_budget(
+        heightmap, budget);
+
+    Ok(rtin_make_terrain_meshes_from_data(heightmap, terrain_mesh_data, load_options))
+}
+
+pub fn rtin_make_terrain_meshes_from_data<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    mut terrain_mesh_data: TerrainMeshData,
+    load_options: &TerrainImageLoadOptions) -> (Mesh, Mesh) {
+
+    if load_options.normal_source == NormalSource::Heightmap {
+        terrain_mesh_data.normals = terrain_mesh_data.vertices.iter()
+            .map(|vertex| heightmap_normal_at(heightmap, vertex.x as u32, vertex.z as u32))
+            .collect();
+    }
+
+    if let Some(hillshade_options) = &load_options.hillshade {
+        terrain_mesh_data.shades = terrain_mesh_data.vertices.iter()
+            .map(|vertex| hillshade_at(heightmap, vertex.x as u32, vertex.z as u32,
+                hillshade_options, load_options.pixel_side_length, load_options.max_image_height))
+            .collect();
+    }
 
     let shaded_mesh = rtin_make_terrain_mesh(
-        &terrain_mesh_data, &rtin_params.load_options, false);
+        &terrain_mesh_data, load_options, false);
     let wireframe_mesh = rtin_make_terrain_mesh(
-        &terrain_mesh_data, &rtin_params.load_options, true);
+        &terrain_mesh_data, load_options, true);
 
     (shaded_mesh, wireframe_mesh)
 }
 
+/// A heightmap with its precomputed RTIN errors. Meshing it again with
+/// another threshold, view or budget skips the errors computation
+pub struct RtinTerrain {
+    heightmap: Heightmap,
+    grid: Arc<RtinGrid>,
+    errors_vec: ErrorsVec,
+}
+
+impl RtinTerrain {
+    pub fn new(heightmap: Heightmap) -> Result<Self, TerrainError> {
+        validate_rtin_heightmap(&heightmap)?;
+
+        let grid = Arc::new(rtin_grid_for_heightmap(&heightmap));
+        RtinTerrain::with_grid(heightmap, grid)
+    }
+
+    /// shares the coordinate tables of terrains of the same grid size,
+    /// panics if `grid` doesn't match the heightmap
+    pub fn with_grid(heightmap: Heightmap, grid: Arc<RtinGrid>) -> Result<Self, TerrainError> {
+        validate_rtin_heightmap(&heightmap)?;
+        assert_eq!(grid.grid_size(), rtin_grid_side(&heightmap) + 1);
+
+        #[cfg(feature = "parallel")]
+        let errors_vec = build_triangle_errors_vec_par(&heightmap, &grid);
+        #[cfg(not(feature = "parallel"))]
+        let errors_vec = build_triangle_errors_vec_with_grid(&heightmap, &grid);
+
+        Ok(RtinTerrain {
+            heightmap,
+            grid,
+            errors_vec
+        })
+    }
+
+    pub fn heightmap(&self) -> &Heightmap {
+        &self.heightmap
+    }
+
+    pub fn grid(&self) -> &Arc<RtinGrid> {
+        &self.grid
+    }
+
+    /// errors indexed by triangle midpoint, see `build_triangle_errors_vec`
+    pub fn errors(&self) -> &[f32] {
+        &self.errors_vec
+    }
+
+    pub fn mesh_data_for_threshold(&self, error_threshold: f32) -> TerrainMeshData {
+        #[cfg(feature = "parallel")]
+        let triangle_bin_ids = rtin_select_triangles_for_heightmap_par(
+            &self.heightmap, &self.grid, &self.errors_vec, error_threshold);
+        #[cfg(not(feature = "parallel"))]
+        let triangle_bin_ids = rtin_select_triangles_for_heightmap(
+            &self.heightmap, &self.grid, &self.errors_vec, error_threshold);
+        rtin_build_terrain_from_triangles(
+            &self.heightmap, &self.grid, &self.errors_vec, triangle_bin_ids)
+    }
+
+    pub fn mesh_data_for_view(
+        &self, view: &RtinView, load_options: &TerrainImageLoadOptions) -> TerrainMeshData {
+        #[cfg(feature = "parallel")]
+        let triangle_bin_ids = rtin_select_triangles_for_view_par(
+            &self.heightmap, &self.grid, &self.errors_vec, view, load_options);
+        #[cfg(not(feature = "parallel"))]
+        let triangle_bin_ids = rtin_select_triangles_for_view(
+            &self.heightmap, &self.grid, &self.errors_vec, view, load_options);
+        rtin_build_terrain_from_triangles(
+            &self.heightmap, &self.grid, &self.errors_vec, triangle_bin_ids)
+    }
+
+    pub fn mesh_data_for_budget(&self, budget: RtinBudget) -> TerrainMeshData {
+        let selection = rtin_select_triangles_for_budget(
+            &self.heightmap, &self.grid, &self.errors_vec, budget);
+        rtin_build_terrain_from_triangles(
+            &self.heightmap, &self.grid, &self.errors_vec, selection.triangles)
+    }
+
+    /// shaded and wireframe meshes for an error threshold
+    pub fn mesh_for_threshold(
+        &self, error_threshold: f32, load_options: &TerrainImageLoadOptions) -> (Mesh, Mesh) {
+        rtin_make_terrain_meshes_from_data(
+            &self.heightmap, self.mesh_data_for_threshold(error_threshold), load_options)
+    }
+
+    /// shaded and wireframe meshes refined for the view of a camera
+    pub fn mesh_for_view(
+        &self, view: &RtinView, load_options: &TerrainImageLoadOptions) -> (Mesh, Mesh) {
+        rtin_make_terrain_meshes_from_data(
+            &self.heightmap, self.mesh_data_for_view(view, load_options), load_options)
+    }
+
+    /// shaded and wireframe meshes as accurate as the budget allows
+    pub fn mesh_for_budget(
+        &self, budget: RtinBudget, load_options: &TerrainImageLoadOptions) -> (Mesh, Mesh) {
+        rtin_make_terrain_meshes_from_data(
+            &self.heightmap, self.mesh_data_for_budget(budget), load_options)
+    }
+
+    /// Triangles chosen by `split_errors` instead of the heightmap errors,
+    /// a triangle being split when its split error is above 1. Vertex
+    /// errors still come from the heightmap
+    pub fn mesh_data_for_split_errors(&self, split_errors: &Vec::<f32>) -> TerrainMeshData {
+        assert_eq!(split_errors.len(), self.errors_vec.len());
+
+        #[cfg(feature = "parallel")]
+        let triangle_bin_ids = rtin_select_triangles_for_heightmap_par(
+            &self.heightmap, &self.grid, split_errors, 1f32);
+        #[cfg(not(feature = "parallel"))]
+        let triangle_bin_ids = rtin_select_triangles_for_heightmap(
+            &self.heightmap, &self.grid, split_errors, 1f32);
+        rtin_build_terrain_from_triangles(
+            &self.heightmap, &self.grid, &self.errors_vec, triangle_bin_ids)
+    }
+
+    /// shaded and wireframe meshes for split errors, see `mesh_data_for_split_errors`
+    pub fn mesh_for_split_errors(
+        &self, split_errors: &Vec::<f32>, load_options: &TerrainImageLoadOptions) -> (Mesh, Mesh) {
+        rtin_make_terrain_meshes_from_data(
+            &self.heightmap, self.mesh_data_for_split_errors(split_errors), load_options)
+    }
+}
+
+/// the `RtinTerrain` of every heightmap used by a terrain entity,
+/// dropped when the heightmap changes or no terrain uses it anymore.
+/// The terrains share their heights with the heightmap assets
+#[derive(Default)]
+pub struct RtinTerrainCache {
+    terrains: HashMap<Handle<Heightmap>, RtinTerrain>,
+}
+
+impl RtinTerrainCache {
+    /// the cached terrain of a loaded heightmap, computing it when missing
+    pub fn get_or_insert(
+        &mut self, 
+        handle: &Handle<Heightmap>, 
+        heightmap: &Heightmap) -> Result<&RtinTerrain, TerrainError> {
+
+        if !self.terrains.contains_key(handle) {
+            validate_rtin_heightmap(heightmap)?;
+
+            let grid_size = rtin_grid_side(heightmap) + 1;
+            let shared_grid = self.terrains.values()
+                .map(RtinTerrain::grid)
+                .find(|grid| grid.grid_size() == grid_size)
+                .cloned();
+            let rtin_terrain = match shared_grid {
+                Some(grid) => RtinTerrain::with_grid(heightmap.clone(), grid)?,
+                None => RtinTerrain::new(heightmap.clone())?,
+            };
+            self.terrains.insert(handle.clone_weak(), rtin_terrain);
+        }
+
+        Ok(&self.terrains[handle])
+    }
+
+    pub fn get(&self, handle: &Handle<Heightmap>) -> Option<&RtinTerrain> {
+        self.terrains.get(handle)
+    }
+
+    pub fn invalidate(&mut self, handle: &Handle<Heightmap>) {
+        self.terrains.remove(handle);
+    }
+
+    /// drop the terrains of the heightmaps not in `used_heightmaps`
+    pub fn retain_used(&mut self, used_heightmaps: &HashSet<&Handle<Heightmap>>) {
+        self.terrains.retain(|handle, _| used_heightmaps.contains(handle));
+    }
+}
+
+/// mesh a terrain, replacing its previous meshes in place so that
+/// the entities using them show the new meshes right away.
+///
+/// The terrain is refined for `view` when given, within its budget
+/// when set and with its error threshold otherwise
+pub fn rtin_update_terrain_meshes(
+    rtin_terrain: &RtinTerrain,
+    terrain: &Terrain,
+    view: Option<&RtinView>,
+    meshes: &mut Assets<Mesh>,
+    terrain_meshes: Option<&TerrainMeshes>) -> TerrainMeshes {
+
+    let new_meshes = match view {
+        Some(view) => rtin_terrain.mesh_for_view(view, &terrain.load_options),
+        None => match terrain.budget {
+            Some(budget) => rtin_terrain.mesh_for_budget(budget, &terrain.load_options),
+            None => rtin_terrain.mesh_for_threshold(
+                terrain.error_threshold, &terrain.load_options),
+        }
+    };
+
+    rtin_store_terrain_meshes(new_meshes, meshes, terrain_meshes)
+}
+
+/// stores the shaded and wireframe meshes, in place of the 
+/// previous ones when given
+pub fn rtin_store_terrain_meshes(
+    (terrain_shaded_mesh, terrain_wireframe_mesh): (Mesh, Mesh),
+    meshes: &mut Assets<Mesh>,
+    terrain_meshes: Option<&TerrainMeshes>) -> TerrainMeshes {
+
+    let terrain_meshes = match terrain_meshes {
+        Some(terrain_meshes) => {
+            meshes.set(terrain_meshes.shaded.clone(), terrain_shaded_mesh);
+            meshes.set(terrain_meshes.wireframe.clone(), terrain_wireframe_mesh);
+            terrain_meshes.clone()
+        }
+        None => TerrainMeshes {
+            shaded: meshes.add(terrain_shaded_mesh),
+            wireframe: meshes.add(terrain_wireframe_mesh),
+        }
+    };
+
+    terrain_meshes
+}
+
+/// returns false while the heightmap is not loaded
+fn rtin_remesh_terrain_entity(
+    commands: &mut Commands,
+    entity: Entity,
+    terrain: &Terrain,
+    view: Option<&RtinView>,
+    terrain_meshes: Option<&TerrainMeshes>,
+    mesh: &mut Handle<Mesh>,
+    heightmaps: &Assets<Heightmap>,
+    rtin_terrains: &mut RtinTerrainCache,
+    meshes: &mut Assets<Mesh>,
+) -> bool {
+    // not loaded yet, meshed by the asset event system later on
+    let heightmap = match heightmaps.get(&terrain.heightmap) {
+        Some(heightmap) => heightmap,
+        None => return false,
+    };
+
+    match rtin_terrains.get_or_insert(&terrain.heightmap, heightmap) {
+        Ok(rtin_terrain) => {
+            let new_terrain_meshes = rtin_update_terrain_meshes(
+                rtin_terrain, terrain, view, meshes, terrain_meshes);
+            if terrain_meshes.is_none() {
+                *mesh = new_terrain_meshes.shaded.clone();
+                commands.insert_one(entity, new_terrain_meshes);
+            }
+        }
+        Err(err) => {
+            // keep showing the previous terrain
+            error!("cannot mesh terrain heightmap: {}", err);
+        }
+    }
+
+    true
+}
+
+/// The view a terrain is re-meshed for outside of the view system, 
+/// screen space error terrains not yet seen by a camera are skipped
+fn rtin_terrain_remesh_view(
+    terrain: &Terrain, terrain_view: Option<&TerrainView>) -> Option<Option<RtinView>> {
+    match (&terrain.screen_space_error, terrain_view) {
+        (None, _) => Some(None),
+        (Some(_), None) => None,
+        (Some(screen_space_error), Some(terrain_view)) => Some(Some(RtinView {
+            max_pixel_error: screen_space_error.max_pixel_error,
+            ..terrain_view.view
+        })),
+    }
+}
+
+/// re-mesh the terrains whose `Terrain` component was added or changed
+pub fn rtin_terrain_changed_system(
+    commands: &mut Commands,
+    heightmaps: Res<Assets<Heightmap>>,
+    mut rtin_terrains: ResMut<RtinTerrainCache>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut terrain_query: Query<
+        (Entity, &Terrain, Option<&TerrainView>, Option<&TerrainMeshes>, &mut Handle<Mesh>), 
+        (Changed<Terrain>, Without<TerrainTile>, Without<RoamTerrain>)>,
+) {
+    for (entity, terrain, terrain_view, terrain_meshes, mut mesh) in terrain_query.iter_mut() {
+        if let Some(view) = rtin_terrain_remesh_view(terrain, terrain_view) {
+            rtin_remesh_terrain_entity(commands, entity, terrain, view.as_ref(), 
+                terrain_meshes, &mut *mesh, &heightmaps, &mut rtin_terrains, &mut meshes);
+        }
+    }
+}
+
+/// re-mesh the terrains whose heightmap finishes loading 
+/// or changes on disk
+pub fn rtin_heightmap_asset_event_system(
+    commands: &mut Commands,
+    mut heightmap_event_reader: Local<EventReader<AssetEvent<Heightmap>>>,
+    heightmap_events: Res<Events<AssetEvent<Heightmap>>>,
+    heightmaps: Res<Assets<Heightmap>>,
+    mut rtin_terrains: ResMut<RtinTerrainCache>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut terrain_query: Query<
+        (Entity, &Terrain, Option<&TerrainView>, Option<&TerrainMeshes>, &mut Handle<Mesh>),
+        (Without<TerrainTile>, Without<RoamTerrain>)>,
+) {
+    for event in heightmap_event_reader.iter(&heightmap_events) {
+        let handle = match event {
+            AssetEvent::Created { handle } | AssetEvent::Modified { handle } => handle,
+            AssetEvent::Removed { handle } => {
+                rtin_terrains.invalidate(handle);
+                continue
+            }
+        };
+
+        rtin_terrains.invalidate(handle);
+
+        for (entity, terrain, terrain_view, terrain_meshes, mut mesh) in terrain_query.iter_mut() {
+            if terrain.heightmap != *handle {
+                continue;
+            }
+            if let Some(view) = rtin_terrain_remesh_view(terrain, terrain_view) {
+                rtin_remesh_terrain_entity(commands, entity, terrain, view.as_ref(), 
+                    terrain_meshes, &mut *mesh, &heightmaps, &mut rtin_terrains, &mut meshes);
+            }
+        }
+    }
+}
+
+/// drop the cached terrains of the heightmaps no terrain entity uses,
+/// e.g. after the entities are despawned or switch heightmaps
+pub fn rtin_terrain_cache_eviction_system(
+    mut rtin_terrains: ResMut<RtinTerrainCache>,
+    terrain_query: Query<&Terrain>,
+) {
+    let used_heightmaps: HashSet<_> = terrain_query.iter()
+        .map(|terrain| &terrain.heightmap)
+        .collect();
+    rtin_terrains.retain_used(&used_heightmaps);
+}
+
+/// re-mesh the screen space error terrains when the camera 
+/// moves far enough or its projection changes. 
+/// Uses the first perspective camera and the primary window
+pub fn rtin_terrain_view_system(
+    commands: &mut Commands,
+    windows: Res<Windows>,
+    heightmaps: Res<Assets<Heightmap>>,
+    mut rtin_terrains: ResMut<RtinTerrainCache>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    camera_query: Query<(&GlobalTransform, &PerspectiveProjection)>,
+    mut terrain_query: Query<(Entity, &Terrain, &GlobalTransform, 
+        Option<&TerrainView>, Option<&TerrainMeshes>, &mut Handle<Mesh>), 
+        (Without<TerrainTile>, Without<RoamTerrain>)>,
+) {
+    let viewport_height = match windows.get_primary() {
+        Some(window) => window.height() as f32,
+        None => return,
+    };
+
+    let (camera_transform, projection) = match camera_query.iter().next() {
+        Some(camera) => camera,
+        None => return,
+    };
+    let camera_translation = camera_transform.translation;
+
+    for (entity, terrain, terrain_transform, terrain_view, terrain_meshes, mut mesh) 
+            in terrain_query.iter_mut() {
+
+        let screen_space_error = match &terrain.screen_space_error {
+            Some(screen_space_error) => screen_space_error,
+            None => continue,
+        };
+
+        let view = RtinView {
+            camera_position: terrain_transform.compute_matrix().inverse()
+                .transform_point3(camera_translation),
+            fov: projection.fov,
+            viewport_height,
+            max_pixel_error: screen_space_error.max_pixel_error,
+        };
+
+        if let Some(terrain_view) = terrain_view {
+            let camera_move = (terrain_view.camera_translation - camera_translation).length();
+
+            if camera_move < screen_space_error.remesh_distance && 
+                    terrain_view.view.has_same_projection(&view) {
+                continue;
+            }
+        }
+
+        if rtin_remesh_terrain_entity(commands, entity, terrain, Some(&view), 
+                terrain_meshes, &mut *mesh, &heightmaps, &mut rtin_terrains, &mut meshes) {
+            commands.insert_one(entity, TerrainView { view, camera_translation });
+        }
+    }
+}
+
 pub fn rtin_make_terrain_mesh(
         terrain_mesh_data: &TerrainMeshData, 
         load_options: &TerrainImageLoadOptions,
@@ -133,46 +763,106 @@ pub fn rtin_make_terrain_mesh(
     let mut vertices : Vec::<[f32; 3]> = Vec::new();
     let mut indices : Vec::<u32> = Vec::new();
     let mut colors  : Vec::<[f32; 3]> = Vec::new();
+    let mut normals : Vec::<[f32; 3]> = Vec::new();
+    let mut uvs : Vec::<[f32; 2]> = Vec::new();
+    let mut morph_heights : Vec::<f32> = Vec::new();
+
+    let skirt = match &load_options.skirt {
+        Some(skirt_options) => {
+            let max_error = terrain_mesh_data.errors.iter().cloned().fold(0f32, f32::max);
+            let depth = skirt_options.depth(max_error) * load_options.max_image_height;
+            Some((mesh_skirt(&terrain_mesh_data.vertices), depth))
+        },
+        None => None,
+    };
+    let triangle_indices: Vec::<u32> = match &skirt {
+        Some((skirt, _)) => terrain_mesh_data.indices.iter()
+            .chain(&skirt.indices)
+            .copied()
+            .collect(),
+        None => terrain_mesh_data.indices.clone(),
+    };
+
     let indices_len = if enable_wireframe {
-        terrain_mesh_data.indices.len() * 2
+        triangle_indices.len() * 2
     } else {
-        terrain_mesh_data.indices.len()
+        triangle_indices.len()
     };
 
     vertices.reserve(terrain_mesh_data.vertices.len());
     colors.reserve(vertices.len());
+    normals.reserve(vertices.len());
+    uvs.reserve(vertices.len());
+    morph_heights.reserve(vertices.len());
     indices.reserve(indices_len);
 
-    let grad = Gradient::new(vec![
-        Hsv::from(LinSrgb::new(1.0, 0.1, 0.1)),
-        Hsv::from(LinSrgb::new(0.1, 1.0, 1.0))
-    ]);
+    let colorizer: &dyn VertexColorizer = match &load_options.colorizer {
+        Some(colorizer) => colorizer.as_ref(),
+        None => &load_options.color_ramp,
+    };
+
+    let pixel_area = load_options.pixel_side_length * load_options.pixel_side_length;
 
-    for vertex in &terrain_mesh_data.vertices {
-        vertices.push(
-            [vertex.x * load_options.pixel_side_length, 
+    for (vertex_index, vertex) in terrain_mesh_data.vertices.iter().enumerate() {
+        let position = Vec3::new(
+            vertex.x * load_options.pixel_side_length, 
             vertex.y * load_options.max_image_height, 
-            vertex.z * load_options.pixel_side_length]);
+            vertex.z * load_options.pixel_side_length);
+        let normal = scale_grid_normal(terrain_mesh_data.normals[vertex_index], 
+            load_options.pixel_side_length, load_options.max_image_height);
+
+        vertices.push([position.x, position.y, position.z]);
+        normals.push([normal.x, normal.y, normal.z]);
+        let morph_height = terrain_mesh_data.morph_heights.get(vertex_index)
+            .copied().unwrap_or(vertex.y);
+        morph_heights.push(morph_height * load_options.max_image_height);
+
+        let color = colorizer.color(&TerrainVertex {
+            position,
+            normal,
+            height: vertex.y,
+            curvature: terrain_mesh_data.curvatures[vertex_index] * 
+                load_options.max_image_height / pixel_area,
+            error: terrain_mesh_data.errors[vertex_index],
+            level: terrain_mesh_data.levels[vertex_index],
+        });
+        let shade = terrain_mesh_data.shades.get(vertex_index).copied().unwrap_or(1f32);
+        colors.push([color[0] * shade, color[1] * shade, color[2] * shade]);
+    }
 
-        let color = grad.get(vertex.y);
-        let raw_float : Srgb::<f32> = 
-            Srgb::<f32>::from_linear(color.into());
-        colors.push([raw_float.red, raw_float.green, raw_float.blue]);
+    for (vertex, uv) in vertices.iter().zip(&terrain_mesh_data.uvs) {
+        let uv = match load_options.uv_tile_size {
+            Some(tile_size) => [vertex[0] / tile_size, vertex[2] / tile_size],
+            None => [uv.x, uv.y],
+        };
+        uvs.push(uv);
     }
 
+    // skirt vertices are their border vertex moved down
+    if let Some((skirt, depth)) = &skirt {
+        for top_vertex in &skirt.top_vertices {
+            let top_vertex = *top_vertex as usize;
+            let [x, y, z] = vertices[top_vertex];
+            vertices.push([x, y - depth, z]);
+            normals.push(normals[top_vertex]);
+            colors.push(colors[top_vertex]);
+            uvs.push(uvs[top_vertex]);
+            morph_heights.push(morph_heights[top_vertex] - depth);
+        }
+    }
 
-    let triangle_number = terrain_mesh_data.indices.len() / 3;
+    let triangle_number = triangle_indices.len() / 3;
 
     if enable_wireframe {
         for i in 0..triangle_number {
             for j in &[0, 1, 1, 2, 2, 0] {
-                indices.push(terrain_mesh_data.indices[i*3+j]);
+                indices.push(triangle_indices[i*3+j]);
             }
         }
     } else {
         for i in 0..triangle_number {
             for j in 0..3 {
-                indices.push(terrain_mesh_data.indices[i*3+j]);
+                indices.push(triangle_indices[i*3+j]);
             }
         }
     }
@@ -181,18 +871,42 @@ pub fn rtin_make_terrain_mesh(
     mesh.set_attribute(
         Mesh::ATTRIBUTE_POSITION,
         VertexAttributeValues::Float3(vertices));
+    mesh.set_attribute(
+        Mesh::ATTRIBUTE_NORMAL,
+        VertexAttributeValues::Float3(normals));
+    mesh.set_attribute(
+        Mesh::ATTRIBUTE_UV_0,
+        VertexAttributeValues::Float2(uvs));
     mesh.set_attribute(
         TerrainMaterial::ATTRIBUTE_COLOR, 
         VertexAttributeValues::Float3(colors)
     );
+    mesh.set_attribute(
+        TerrainMaterial::ATTRIBUTE_MORPH_HEIGHT, 
+        VertexAttributeValues::Float(morph_heights)
+    );
     mesh.set_indices(Some(Indices::U32(indices)));
 
     mesh
 }
 
+/// RTIN mesh in heightmap grid coordinates, normals are
+/// scaled along with the vertices by `scale_grid_normal`.
+/// UVs go from 0 to 1 across the heightmap pixels, the
+/// other per vertex values feed the `TerrainVertex` colorized.
+/// Shades are empty unless a hillshade is requested. Morph heights
+/// are the vertex heights once the diamonds whose children are all
+/// selected are merged, for geomorphing, see `rtin_morph_heights`
 pub struct TerrainMeshData {
    pub vertices: Vec::<Vec3>,
-   pub indices: Vec::<u32>
+   pub indices: Vec::<u32>,
+   pub normals: Vec::<Vec3>,
+   pub uvs: Vec::<Vec2>,
+   pub curvatures: Vec::<f32>,
+   pub errors: Vec::<f32>,
+   pub levels: Vec::<u32>,
+   pub shades: Vec::<f32>,
+   pub morph_heights: Vec::<f32>,
 }
 
 trait VecClamp {
@@ -209,8 +923,8 @@ impl<T> VecClamp for na::Vector2<T> where T: Scalar + Ord + Copy {
     }
 }
 
-pub fn sample_heightmap_height_corner_mean(
-    heightmap: &HeightMapU16, corner_u32: Vec2u32) -> f32 {        
+pub fn sample_heightmap_height_corner_mean<H: HeightSource + ?Sized>(
+    heightmap: &H, corner_u32: Vec2u32) -> f32 {        
 
     let mut new_corner = corner_u32;
 
@@ -222,29 +936,137 @@ pub fn sample_heightmap_height_corner_mean(
         new_corner[1] = heightmap.height() - 1;
     }
 
-    heightmap.get_pixel(
-        new_corner[0], new_corner[1]).0[0] as f32
-        / std::u16::MAX as f32
+    heightmap.height_at(new_corner[0], new_corner[1])
+}
 
+/// texture coordinates of a grid vertex, the centers of the first 
+/// and last heightmap pixels map to 0 and 1
+pub fn heightmap_pixel_uv<H: HeightSource + ?Sized>(
+    heightmap: &H, x: f32, y: f32) -> Vec2 {
+    let u_scale = (heightmap.width() - 1).max(1) as f32;
+    let v_scale = (heightmap.height() - 1).max(1) as f32;
 
+    Vec2::new(
+        (x / u_scale).min(1f32),
+        (y / v_scale).min(1f32))
 }
 
-pub fn rtin_build_terrain_from_heightmap(
-    heightmap: &HeightMapU16, error_threshold: f32) -> TerrainMeshData {
-    let errors_vec = build_triangle_errors_vec(heightmap);
+pub fn rtin_build_terrain_from_heightmap<H: HeightSource + ?Sized>(
+    heightmap: &H, error_threshold: f32) -> TerrainMeshData {
+    let grid = rtin_grid_for_heightmap(heightmap);
+    let errors_vec = build_triangle_errors_vec_with_grid(heightmap, &grid);
+
+    let triangle_bin_ids = rtin_select_triangles_for_heightmap(
+        heightmap, &grid, &errors_vec, error_threshold);
+
+    rtin_build_terrain_from_triangles(heightmap, &grid, &errors_vec, triangle_bin_ids)
+}
+
+pub fn rtin_build_terrain_from_heightmap_for_view<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    view: &RtinView, 
+    load_options: &TerrainImageLoadOptions) -> TerrainMeshData {
+    let grid = rtin_grid_for_heightmap(heightmap);
+    let errors_vec = build_triangle_errors_vec_with_grid(heightmap, &grid);
+
+    let triangle_bin_ids = rtin_select_triangles_for_view(
+        heightmap, &grid, &errors_vec, view, load_options);
+
+    rtin_build_terrain_from_triangles(heightmap, &grid, &errors_vec, triangle_bin_ids)
+}
+
+pub fn rtin_build_terrain_from_heightmap_for_budget<H: HeightSource + ?Sized>(
+    heightmap: &H, budget: RtinBudget) -> TerrainMeshData {
+    let grid = rtin_grid_for_heightmap(heightmap);
+    let errors_vec = build_triangle_errors_vec_with_grid(heightmap, &grid);
+
+    let selection = rtin_select_triangles_for_budget(
+        heightmap, &grid, &errors_vec, budget);
+
+    rtin_build_terrain_from_triangles(heightmap, &grid, &errors_vec, selection.triangles)
+}
+
+/// Geomorph target of the midpoint of the diamond `bin_id` was split
+/// from, with the other children of that diamond: the mean height of the
+/// diamond hypotenuse ends when every child touching the heightmap is
+/// selected, so that merging the diamond back removes the midpoint.
+///
+/// `None` for the roots and when part of the diamond is split further
+/// or merged, the midpoint then keeps its own height
+pub fn rtin_diamond_morph_height<H, F>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    extent: Vec2u32,
+    bin_id: BinId,
+    is_selected: F) -> Option<(Vec2u32, f32)>
+    where H: HeightSource + ?Sized, F: Fn(BinId) -> bool {
+
+    let parent_bin_id = get_triangle_parent_bin_id(bin_id)?;
+    let diamond = std::iter::once(parent_bin_id)
+        .chain(get_triangle_base_neighbour_bin_id(parent_bin_id));
+
+    for diamond_bin_id in diamond {
+        let (right_child_bin_id, left_child_bin_id) = 
+            get_triangle_children_bin_ids(diamond_bin_id);
+        for child_bin_id in &[right_child_bin_id, left_child_bin_id] {
+            let touches_heightmap = triangle_extent_overlap(
+                grid.triangle_coords(*child_bin_id), extent) != TriangleExtentOverlap::Outside;
+            if touches_heightmap && !is_selected(*child_bin_id) {
+                return None;
+            }
+        }
+    }
+
+    let (a, b, _) = grid.triangle_coords(parent_bin_id);
+    let morph_height = (sample_heightmap_height_corner_mean(heightmap, a) +
+        sample_heightmap_height_corner_mean(heightmap, b)) / 2f32;
+    Some(((a + b) / 2, morph_height))
+}
+
+/// geomorph targets of the vertices the last merge of the selection
+/// removes, by vertex id `y * grid_size + x`
+pub fn rtin_morph_heights<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    triangle_bin_ids: &[BinId]) -> HashMap::<u32, f32> {
+
+    let extent = rtin_grid_extent(heightmap);
+    let selected: HashSet::<BinId> = triangle_bin_ids.iter().copied().collect();
+    let grid_size = grid.grid_size();
+
+    triangle_bin_ids.iter()
+        .filter_map(|bin_id| rtin_diamond_morph_height(
+            heightmap, grid, extent, *bin_id, |bin_id| selected.contains(&bin_id)))
+        .map(|(mid_point, morph_height)| (mid_point[1] * grid_size + mid_point[0], morph_height))
+        .collect()
+}
+
+fn rtin_build_terrain_from_triangles<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &ErrorsVec, 
+    triangle_bin_ids: Vec::<BinId>) -> TerrainMeshData {
 
     let mut vertices = Vec::<Vec3>::new();
     let mut indices = Vec::<u32>::new();
     let mut vertices_array_position = HashMap::<u32, usize>::new(); 
 
-    let triangle_bin_ids = rtin_select_triangles_for_heightmap(
-        heightmap, &errors_vec, error_threshold);
+    let mut errors = Vec::<f32>::new();
+    let mut levels = Vec::<u32>::new();
+
+    let grid_size = grid.grid_size();
+    let vertex_morph_heights = rtin_morph_heights(heightmap, grid, &triangle_bin_ids);
 
     for triangle_bin_id in triangle_bin_ids {
-        let grid_size = heightmap.width() + 1;
-        let triangle_coords = get_triangle_coords(triangle_bin_id, grid_size);
+        let triangle_coords = grid.triangle_coords(triangle_bin_id);
         let new_vertices = &[triangle_coords.0, triangle_coords.1, triangle_coords.2];
 
+        let triangle_error = rtin_triangle_error(
+            triangle_coords, errors_vec, grid_size);
+        // border triangles are only selected when they can't be split
+        let triangle_error = if triangle_error.is_finite() { triangle_error } else { 0f32 };
+        let triangle_level = bin_id_to_level(triangle_bin_id);
+
         for new_vertex in new_vertices {
             let vertex_id = new_vertex[1] * grid_size + new_vertex[0];
 
@@ -264,35 +1086,372 @@ pub fn rtin_build_terrain_from_heightmap(
                     new_vertex[1] as f32,
                 );
                 vertices.push(new_vertex_3d);
+                errors.push(0f32);
+                levels.push(0);
                 new_vertex_index
             };
             indices.push(vertex_index as u32);
+
+            errors[vertex_index] = errors[vertex_index].max(triangle_error);
+            levels[vertex_index] = levels[vertex_index].max(triangle_level);
         }
 
     }
 
+    let normals = smooth_vertex_normals(&vertices, &indices);
+    let uvs = vertices.iter()
+        .map(|vertex| heightmap_pixel_uv(heightmap, vertex.x, vertex.z))
+        .collect();
+    let curvatures = vertices.iter()
+        .map(|vertex| heightmap_laplacian_at(heightmap, vertex.x as u32, vertex.z as u32))
+        .collect();
+    let morph_heights = vertices.iter()
+        .map(|vertex| *vertex_morph_heights
+            .get(&(vertex.z as u32 * grid_size + vertex.x as u32))
+            .unwrap_or(&vertex.y))
+        .collect();
+
     TerrainMeshData {
         vertices, 
-        indices
+        indices,
+        normals,
+        uvs,
+        curvatures,
+        errors,
+        levels,
+        shades: Vec::new(),
+        morph_heights,
     }
 }
 
-pub fn rtin_select_triangles_for_heightmap(
-    heightmap: &HeightMapU16, 
+pub fn rtin_select_triangles_for_heightmap<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
     errors_vec: &ErrorsVec, error_threshold: f32) -> Vec::<BinId> {
 
     let mut triangles = Vec::<BinId>::new();
 
     rtin_select_triangles_for_heightmap_process_triangle(
-        heightmap, &errors_vec, &mut triangles, 
+        heightmap, grid, &errors_vec, &mut triangles, 
         0, error_threshold);
     rtin_select_triangles_for_heightmap_process_triangle(
-        heightmap, &errors_vec, &mut triangles, 
+        heightmap, grid, &errors_vec, &mut triangles, 
         1, error_threshold);
 
     triangles
 }
 
+/// same as `rtin_select_triangles_for_heightmap`, using all cores
+#[cfg(feature = "parallel")]
+pub fn rtin_select_triangles_for_heightmap_par<H: HeightSource + Sync + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &ErrorsVec, error_threshold: f32) -> Vec::<BinId> {
+
+    rtin_select_triangles_par(heightmap, grid, errors_vec, 
+        &|_triangle, triangle_error| triangle_error <= error_threshold)
+}
+
+/// A camera for the screen space error refinement, in the terrain local
+/// space: the terrain spans x and z from 0 and y from 0 to `max_image_height`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtinView {
+    pub camera_position: Vec3,
+    /// vertical field of view, in radians
+    pub fov: f32,
+    /// in pixels
+    pub viewport_height: f32,
+    /// largest error allowed once projected on screen, in pixels
+    pub max_pixel_error: f32,
+}
+
+impl RtinView {
+    /// Whether a triangle, once projected, stays within the pixel error.
+    ///
+    /// The distance is measured to a sphere around the triangle midpoint,
+    /// large enough to contain the spheres of the children. Parents are
+    /// then never more accurate than their children, and diamond partners
+    /// share the midpoint, so the selection stays crack free
+    pub fn is_triangle_accurate(
+        &self, 
+        triangle: TriangleU32, 
+        triangle_error: f32, 
+        load_options: &TerrainImageLoadOptions) -> bool {
+
+        let distance = self.triangle_distance(triangle, load_options);
+        let world_error = triangle_error * load_options.max_image_height;
+
+        world_error * self.pixels_per_unit() <= self.max_pixel_error * distance
+    }
+
+    /// How far the camera can move before `is_triangle_accurate` may give
+    /// another answer for the triangle: the distance to the sphere of the
+    /// triangle changes at most as much as the camera moves
+    pub fn accuracy_slack(
+        &self, 
+        triangle: TriangleU32, 
+        triangle_error: f32, 
+        load_options: &TerrainImageLoadOptions) -> f32 {
+
+        let world_error = triangle_error * load_options.max_image_height;
+        let accurate_distance = world_error * self.pixels_per_unit() / self.max_pixel_error;
+        let slack = (self.triangle_distance(triangle, load_options) - accurate_distance).abs();
+
+        // NaN errors and no pixel error allowed don't depend on the distance
+        if slack.is_nan() { f32::INFINITY } else { slack }
+    }
+
+    /// whether both views project the same way, whatever their camera position
+    pub fn has_same_projection(&self, other: &RtinView) -> bool {
+        self.fov == other.fov &&
+            self.viewport_height == other.viewport_height &&
+            self.max_pixel_error == other.max_pixel_error
+    }
+
+    fn pixels_per_unit(&self) -> f32 {
+        self.viewport_height / (2.0 * (self.fov / 2.0).tan())
+    }
+
+    /// distance from the camera to the sphere of the triangle,
+    /// see `is_triangle_accurate`
+    fn triangle_distance(&self, triangle: TriangleU32, load_options: &TerrainImageLoadOptions) -> f32 {
+        let a = vecu32_to_vecf32(triangle.0) * load_options.pixel_side_length;
+        let b = vecu32_to_vecf32(triangle.1) * load_options.pixel_side_length;
+        let midpoint = (a + b) / 2.0;
+        let radius = 1.25 * (b - a).length();
+
+        let camera_offset = self.camera_position - midpoint;
+        let horizontal_distance = (camera_offset.x * camera_offset.x + 
+            camera_offset.z * camera_offset.z).sqrt();
+        let horizontal_distance = (horizontal_distance - radius).max(0f32);
+        let vertical_distance = (self.camera_position.y - load_options.max_image_height)
+            .max(-self.camera_position.y)
+            .max(0f32);
+
+        (horizontal_distance * horizontal_distance + 
+            vertical_distance * vertical_distance).sqrt()
+    }
+
+    /// Errors at or below the first bound are accurate for every triangle
+    /// of a grid of `grid_side` pixels, errors above the second one for none
+    pub fn error_bounds(&self, grid_side: u32, load_options: &TerrainImageLoadOptions) -> (f32, f32) {
+        let side = grid_side as f32 * load_options.pixel_side_length;
+        let vertical_distance = (self.camera_position.y - load_options.max_image_height)
+            .max(-self.camera_position.y)
+            .max(0f32);
+        let farthest_x = self.camera_position.x.max(side - self.camera_position.x);
+        let farthest_z = self.camera_position.z.max(side - self.camera_position.z);
+        let max_distance = (farthest_x * farthest_x + farthest_z * farthest_z + 
+            vertical_distance * vertical_distance).sqrt();
+
+        let error_at = |distance: f32| self.max_pixel_error * distance / 
+            (self.pixels_per_unit() * load_options.max_image_height);
+
+        (error_at(vertical_distance), error_at(max_distance))
+    }
+}
+
+/// selects the triangles of the terrain seen from `view`, nearby
+/// triangles are smaller than distant ones
+pub fn rtin_select_triangles_for_view<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &ErrorsVec, 
+    view: &RtinView,
+    load_options: &TerrainImageLoadOptions) -> Vec::<BinId> {
+
+    let mut triangles = Vec::<BinId>::new();
+
+    let is_accurate = |triangle, triangle_error| view.is_triangle_accurate(
+        triangle, triangle_error, load_options);
+
+    rtin_select_triangles_process_triangle(
+        heightmap, grid, &errors_vec, &mut triangles, 0, &is_accurate);
+    rtin_select_triangles_process_triangle(
+        heightmap, grid, &errors_vec, &mut triangles, 1, &is_accurate);
+
+    triangles
+}
+
+/// same as `rtin_select_triangles_for_view`, using all cores
+#[cfg(feature = "parallel")]
+pub fn rtin_select_triangles_for_view_par<H: HeightSource + Sync + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &ErrorsVec, 
+    view: &RtinView,
+    load_options: &TerrainImageLoadOptions) -> Vec::<BinId> {
+
+    rtin_select_triangles_par(heightmap, grid, errors_vec, 
+        &|triangle, triangle_error| view.is_triangle_accurate(
+            triangle, triangle_error, load_options))
+}
+
+/// largest mesh allowed by `rtin_select_triangles_for_budget`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtinBudget {
+    Triangles(usize),
+    Vertices(usize),
+}
+
+/// the most accurate selection within a budget
+#[derive(Debug, Clone, PartialEq)]
+pub struct RtinBudgetSelection {
+    pub triangles: Vec::<BinId>,
+    /// threshold giving the same selection with `rtin_select_triangles_for_heightmap`
+    pub error_threshold: f32,
+    /// largest error of the selected triangles
+    pub max_error: f32,
+}
+
+/// A triangle waiting to be split, ordered by error. NaN errors, e.g.
+/// from no-data heights, come after every other error like the
+/// triangles that must be split
+#[derive(Debug, Clone, Copy)]
+pub struct RtinSplitCandidate {
+    pub error: f32,
+    pub bin_id: BinId,
+}
+
+impl PartialEq for RtinSplitCandidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for RtinSplitCandidate {}
+
+impl PartialOrd for RtinSplitCandidate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RtinSplitCandidate {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let error_ordering = match (self.error.is_nan(), other.error.is_nan()) {
+            (true, true) => std::cmp::Ordering::Equal,
+            (true, false) => std::cmp::Ordering::Greater,
+            (false, true) => std::cmp::Ordering::Less,
+            (false, false) => self.error.partial_cmp(&other.error).unwrap(),
+        };
+        error_ordering.then(self.bin_id.cmp(&other.bin_id))
+    }
+}
+
+/// Selects the most accurate triangles fitting in the budget, splitting
+/// the triangle of largest error first. 
+///
+/// Triangles of equal error are split together, so the selection is the
+/// one of an error threshold and stays crack free. A budget smaller than
+/// the triangles crossing the heightmap border gives the coarsest mesh
+pub fn rtin_select_triangles_for_budget<H: HeightSource + ?Sized>(
+    heightmap: &H, 
+    grid: &RtinGrid,
+    errors_vec: &ErrorsVec, 
+    budget: RtinBudget) -> RtinBudgetSelection {
+
+    let grid_size = grid.grid_size();
+    let extent = rtin_grid_extent(heightmap);
+
+    // the selection only keeps the triangles touching the heightmap
+    let mut triangle_count = 0usize;
+    let mut vertex_references = HashMap::<u32, u32>::new();
+
+    let add_triangle = |bin_id: BinId, sign: i32, 
+            triangle_count: &mut usize, vertex_references: &mut HashMap::<u32, u32>| {
+        let triangle = grid.triangle_coords(bin_id);
+        if triangle_extent_overlap(triangle, extent) == TriangleExtentOverlap::Outside {
+            return;
+        }
+
+        for vertex in &[triangle.0, triangle.1, triangle.2] {
+            let vertex_id = vertex[1] * grid_size + vertex[0];
+            if sign > 0 {
+                *vertex_references.entry(vertex_id).or_insert(0) += 1;
+            } else {
+                let references = vertex_references.get_mut(&vertex_id).unwrap();
+                *references -= 1;
+                if *references == 0 {
+                    vertex_references.remove(&vertex_id);
+                }
+            }
+        }
+
+        if sign > 0 {
+            *triangle_count += 1;
+        } else {
+            *triangle_count -= 1;
+        }
+    };
+
+    let mut candidates = std::collections::BinaryHeap::new();
+    let push_candidate = |bin_id: BinId, candidates: &mut std::collections::BinaryHeap<RtinSplitCandidate>| {
+        if !grid.has_children(bin_id) {
+            return;
+        }
+        let triangle = grid.triangle_coords(bin_id);
+        let error = rtin_triangle_error(triangle, errors_vec, grid_size);
+        // the selection splits NaN errors whatever the threshold, like the border triangles
+        let error = if error.is_nan() { f32::INFINITY } else { error };
+        // exact triangles are never worth splitting
+        if error > 0f32 {
+            candidates.push(RtinSplitCandidate { error, bin_id });
+        }
+    };
+
+    for root_bin_id in &[index_to_bin_id(0), index_to_bin_id(1)] {
+        add_triangle(*root_bin_id, 1, &mut triangle_count, &mut vertex_references);
+        push_candidate(*root_bin_id, &mut candidates);
+    }
+
+    let within_budget = |triangle_count: usize, vertex_count: usize| match budget {
+        RtinBudget::Triangles(max_triangles) => triangle_count <= max_triangles,
+        RtinBudget::Vertices(max_vertices) => vertex_count <= max_vertices,
+    };
+
+    let mut error_threshold = 0f32;
+
+    while let Some(group_error) = candidates.peek().map(|candidate| candidate.error) {
+        // children can have the same error as their parent,
+        // they join the group as they are pushed
+        while candidates.peek().map_or(false, |candidate| candidate.error >= group_error) {
+            let bin_id = candidates.pop().unwrap().bin_id;
+            let (right_child_bin_id, left_child_bin_id) = 
+                get_triangle_children_bin_ids(bin_id);
+
+            add_triangle(bin_id, -1, &mut triangle_count, &mut vertex_references);
+            for child_bin_id in &[left_child_bin_id, right_child_bin_id] {
+                add_triangle(*child_bin_id, 1, &mut triangle_count, &mut vertex_references);
+                push_candidate(*child_bin_id, &mut candidates);
+            }
+        }
+
+        if !within_budget(triangle_count, vertex_references.len()) {
+            error_threshold = group_error;
+            break;
+        }
+    }
+
+    // the border triangles must be split whatever the budget
+    if error_threshold == f32::INFINITY {
+        error_threshold = f32::MAX;
+    }
+
+    let triangles = rtin_select_triangles_for_heightmap(
+        heightmap, grid, errors_vec, error_threshold);
+    let max_error = triangles.iter()
+        .map(|bin_id| rtin_triangle_error(
+            grid.triangle_coords(*bin_id), errors_vec, grid_size))
+        .filter(|error| error.is_finite())
+        .fold(0f32, f32::max);
+
+    RtinBudgetSelection {
+        triangles,
+        error_threshold,
+        max_error,
+    }
+}
 
 const fn num_bits<T>() -> usize { std::mem::size_of::<T>() * 8 }
 fn log_2(x: u32) -> u32 {
@@ -300,15 +1459,53 @@ fn log_2(x: u32) -> u32 {
 }
 
 
-pub fn build_triangle_errors_vec(heightmap: &HeightMapU16) -> Vec::<f32> {
+/// Raises the error of every triangle to the errors of its children, 
+/// after some entries of a complete errors vector were raised. 
+///
+/// Keeps a triangle split whenever one of its children is
+pub fn propagate_triangle_errors_vec(grid: &RtinGrid, errors_vec: &mut Vec::<f32>) {
+    let side = grid.grid_size() - 1;
+    let number_of_levels = log_2(side)*2;
+    // the children of the last level are leaves, without errors
+    let parents_end: u32 = get_index_level_start(number_of_levels.saturating_sub(1));
+
+    for triangle_index in (0..parents_end).rev() {
+        let triangle_bin_id = index_to_bin_id(triangle_index);
+        let (right_child_bin_id, left_child_bin_id) = 
+            get_triangle_children_bin_ids(triangle_bin_id);
+
+        let grid_size = grid.grid_size();
+        let errors_vec_index = triangle_coords_errors_vec_index(
+            grid.triangle_coords(triangle_bin_id), grid_size);
+        let right_error = errors_vec[triangle_coords_errors_vec_index(
+            grid.triangle_coords(right_child_bin_id), grid_size)];
+        let left_error = errors_vec[triangle_coords_errors_vec_index(
+            grid.triangle_coords(left_child_bin_id), grid_size)];
+
+        errors_vec[errors_vec_index] = 
+            errors_vec[errors_vec_index].max(left_error).max(right_error);
+    }
+}
+
+pub fn build_triangle_errors_vec<H: HeightSource + ?Sized>(heightmap: &H) -> Vec::<f32> {
+    assert_valid_rtin_heightmap(heightmap);
+
+    build_triangle_errors_vec_with_grid(heightmap, &rtin_grid_for_heightmap(heightmap))
+}
+
+/// same as `build_triangle_errors_vec`, reusing the coordinate tables of `grid`
+pub fn build_triangle_errors_vec_with_grid<H: HeightSource + ?Sized>(
+    heightmap: &H, grid: &RtinGrid) -> Vec::<f32> {
     assert_valid_rtin_heightmap(heightmap);
 
 
-    let side = heightmap.width();
+    let side = rtin_grid_side(heightmap);
     let grid_size = side+1;
+    assert_eq!(grid.grid_size(), grid_size);
+    let extent = rtin_grid_extent(heightmap);
     let number_of_triangles = side * side * 2 - 2;
     let number_of_levels = log_2(side)*2;
-    let last_level = number_of_levels - 1;
+    let last_level = number_of_levels.saturating_sub(1);
 
     let last_level_index_start = get_index_level_start(last_level);
     
@@ -316,54 +1513,121 @@ pub fn build_triangle_errors_vec(heightmap: &HeightMapU16) -> Vec::<f32> {
     errors_vec.resize( (grid_size*grid_size) as usize, 0.0f32);
 
     for triangle_index in (0..number_of_triangles).rev() {
+        let (this_triangle_mid_point_error_vec_index, this_triangle_error) = 
+            triangle_error_with_children(
+                heightmap, grid, extent, &errors_vec, triangle_index, last_level_index_start);
 
-        let triangle_bin_id = index_to_bin_id(triangle_index);
+        errors_vec[this_triangle_mid_point_error_vec_index] = 
+            errors_vec[this_triangle_mid_point_error_vec_index].max(this_triangle_error);
+    }
 
-        let midpoint =
-            pixel_coords_for_triangle_mid_point(triangle_bin_id, grid_size);
+    errors_vec
+}
 
-        let triangle_coords = get_triangle_coords(triangle_bin_id, grid_size);
-        let h0 = sample_heightmap_height_corner_mean(heightmap, triangle_coords.0);
-        let h1 = sample_heightmap_height_corner_mean(heightmap, triangle_coords.1);
-        let midpoint_interpolated = (h1+h0)/2.0;
-        let midpoint_height = sample_heightmap_height_corner_mean(heightmap, midpoint);
+/// same as `build_triangle_errors_vec_with_grid`, the triangles of each
+/// level are processed concurrently. The errors are bit-identical
+#[cfg(feature = "parallel")]
+pub fn build_triangle_errors_vec_par<H: HeightSource + Sync + ?Sized>(
+    heightmap: &H, grid: &RtinGrid) -> Vec::<f32> {
+    use rayon::prelude::*;
 
-        let this_triangle_error = (midpoint_interpolated - midpoint_height).abs();
+    // bounds the memory of the errors waiting to be stored
+    const CHUNK_SIZE: u32 = 1 << 20;
 
-        let this_triangle_mid_point_error_vec_index = triangle_errors_vec_index(
-            triangle_bin_id, grid_size);
+    assert_valid_rtin_heightmap(heightmap);
 
-        // println!("Processing triangle {:b} of coords {:?} with error index {}",
-        //      triangle_bin_id, triangle_coords, this_triangle_mid_point_error_vec_index);
+    let side = rtin_grid_side(heightmap);
+    let grid_size = side+1;
+    assert_eq!(grid.grid_size(), grid_size);
+    let extent = rtin_grid_extent(heightmap);
+    let number_of_levels = log_2(side)*2;
+    let last_level = number_of_levels.saturating_sub(1);
 
-        if triangle_index >= last_level_index_start {
-            errors_vec[this_triangle_mid_point_error_vec_index] = this_triangle_error;
-        } else {
-            let (right_child_bin_id, left_child_bin_id) = 
-                get_triangle_children_bin_ids(triangle_bin_id);
+    let last_level_index_start = get_index_level_start(last_level);
 
-            let right_errors_vec_index = triangle_errors_vec_index(
-                right_child_bin_id, grid_size);
-            let left_errors_vec_index = triangle_errors_vec_index(
-                left_child_bin_id, grid_size);
-            
+    let mut errors_vec = Vec::new();
+    errors_vec.resize( (grid_size*grid_size) as usize, 0.0f32);
 
-            let prev_error = errors_vec[this_triangle_mid_point_error_vec_index];
-            let right_error = errors_vec[right_errors_vec_index];
-            let left_error = errors_vec[left_errors_vec_index];
+    // a level only reads the errors of the one below, 
+    // and max doesn't depend on the order of the writes
+    for level in (0..number_of_levels).rev() {
+        let level_end: u32 = get_index_level_start(level + 1);
+        let mut chunk_start = get_index_level_start(level);
+
+        while chunk_start < level_end {
+            let chunk_end = level_end.min(chunk_start + CHUNK_SIZE);
+            let chunk_errors: Vec<(usize, f32)> = (chunk_start..chunk_end)
+                .into_par_iter()
+                .map(|triangle_index| triangle_error_with_children(
+                    heightmap, grid, extent, &errors_vec, triangle_index, last_level_index_start))
+                .collect();
+
+            for (errors_vec_index, triangle_error) in chunk_errors {
+                errors_vec[errors_vec_index] = errors_vec[errors_vec_index].max(triangle_error);
+            }
 
-            errors_vec[this_triangle_mid_point_error_vec_index] = 
-                prev_error.max(left_error).max(right_error).max(this_triangle_error);
+            chunk_start = chunk_end;
         }
-       
     }
 
     errors_vec
 }
 
+/// the errors vector index of a triangle midpoint, and the error of the 
+/// triangle merged with the ones of its children, already in `errors_vec`
+fn triangle_error_with_children<H: HeightSource + ?Sized>(
+    heightmap: &H,
+    grid: &RtinGrid,
+    extent: Vec2u32,
+    errors_vec: &ErrorsVec,
+    triangle_index: u32,
+    last_level_index_start: u32) -> (usize, f32) {
+
+    let grid_size = grid.grid_size();
+    let triangle_bin_id = index_to_bin_id(triangle_index);
+
+    let triangle_coords = grid.triangle_coords(triangle_bin_id);
+    let midpoint = (triangle_coords.0 + triangle_coords.1) / 2;
+
+    let h0 = sample_heightmap_height_corner_mean(heightmap, triangle_coords.0);
+    let h1 = sample_heightmap_height_corner_mean(heightmap, triangle_coords.1);
+    let midpoint_interpolated = (h1+h0)/2.0;
+    let midpoint_height = sample_heightmap_height_corner_mean(heightmap, midpoint);
+
+    // triangles crossing the heightmap border must always be split,
+    // so that once clipped no triangle lies outside the data
+    let this_triangle_error = match triangle_extent_overlap(triangle_coords, extent) {
+        TriangleExtentOverlap::Crossing => f32::INFINITY,
+        _ => (midpoint_interpolated - midpoint_height).abs()
+    };
+
+    let this_triangle_mid_point_error_vec_index = triangle_coords_errors_vec_index(
+        triangle_coords, grid_size);
+
+    if triangle_index >= last_level_index_start {
+        (this_triangle_mid_point_error_vec_index, this_triangle_error)
+    } else {
+        let (right_child_bin_id, left_child_bin_id) = 
+            get_triangle_children_bin_ids(triangle_bin_id);
+
+        let right_errors_vec_index = triangle_coords_errors_vec_index(
+            grid.triangle_coords(right_child_bin_id), grid_size);
+        let left_errors_vec_index = triangle_coords_errors_vec_index(
+            grid.triangle_coords(left_child_bin_id), grid_size);
+
+        let right_error = errors_vec[right_errors_vec_index];
+        let left_error = errors_vec[left_errors_vec_index];
+
+        (this_triangle_mid_point_error_vec_index, 
+            left_error.max(right_error).max(this_triangle_error))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::heightmap::{FnHeightSource, HeightMapU16, sine_height_source};
+    use crate::skirt::SkirtOptions;
 
     #[test]
     fn test_build_triangle_error_vec() {
@@ -377,8 +1641,395 @@ mod tests {
 
         let error_vec = build_triangle_errors_vec(&heightmap);
 
-        assert_eq!(error_vec, 
-         vec![0.0, 0.1, 0.3, 0.4, 0.5, 0.6]);
+        // a side 1 grid has no midpoint to be wrong at
+        assert_eq!(error_vec, vec![0.0; 4]);
+
+        let heightmap_data = vec![
+            0u16,      0u16, 0u16,
+            0u16, 65535u16, 0u16,
+            0u16,      0u16, 0u16,
+        ];
+
+        let heightmap  = 
+            HeightMapU16::from_vec(3, 3, heightmap_data).unwrap();
+
+        let error_vec = build_triangle_errors_vec(&heightmap);
+
+        // errors are indexed by midpoint, only the center is off the plane
+        assert_eq!(error_vec, vec![
+            0.0, 0.0, 0.0,
+            0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0,
+        ]);
+    }
+
+    #[test]
+    fn test_build_terrain_from_non_square_heightmap() {
+        let (width, height) = (3u32, 5u32);
+        let heightmap_data = (0..width*height)
+            .map(|i| ((i * 7919) % 65536) as u16)
+            .collect();
+
+        let heightmap  = 
+            HeightMapU16::from_vec(width, height, heightmap_data).unwrap();
+
+        assert_eq!(rtin_grid_side(&heightmap), 4);
+        assert_eq!(rtin_grid_extent(&heightmap), Vec2u32::new(2, 4));
+
+        for error_threshold in &[0.0f32, 0.5, 1.0] {
+            let mesh_data = rtin_build_terrain_from_heightmap(
+                &heightmap, *error_threshold);
+
+            for vertex in &mesh_data.vertices {
+                assert!(vertex.x <= 2.0);
+                assert!(vertex.z <= 4.0);
+            }
+
+            let covered_area : f32 = mesh_data.indices.chunks(3).map(|triangle| {
+                let a = mesh_data.vertices[triangle[0] as usize];
+                let b = mesh_data.vertices[triangle[1] as usize];
+                let c = mesh_data.vertices[triangle[2] as usize];
+                ((b.x - a.x) * (c.z - a.z) - (c.x - a.x) * (b.z - a.z)).abs() / 2.0
+            }).sum();
+
+            assert_eq!(covered_area, 8.0);
+        }
+    }
+
+    #[test]
+    fn test_rtin_vertices_within_last_pixel() {
+        for (width, height) in &[(5u32, 5u32), (6, 3), (3, 5), (17, 10), (20, 33)] {
+            let heightmap = FnHeightSource::new(*width, *height, 
+                |x, y| ((x * 31 + y * 17) % 7) as f32 * 0.1);
+            assert_eq!(rtin_grid_extent(&heightmap), Vec2u32::new(width - 1, height - 1));
+
+            for error_threshold in &[0.0f32, 0.2, 1.0] {
+                let mesh_data = rtin_build_terrain_from_heightmap(&heightmap, *error_threshold);
+                assert!(!mesh_data.vertices.is_empty());
+                for vertex in &mesh_data.vertices {
+                    assert!(vertex.x <= (width - 1) as f32);
+                    assert!(vertex.z <= (height - 1) as f32);
+                }
+                assert!(mesh_data.vertices.iter().any(|vertex| 
+                    vertex.x == (width - 1) as f32 && vertex.z == (height - 1) as f32));
+            }
+        }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_rtin_load_terrain_errors() {
+        let missing_file = rtin_load_terrain("does_not_exist.png", 
+            HeightEncoding::default(), HeightChannel::default(), 0.0, &TerrainImageLoadOptions::default());
+        assert!(matches!(missing_file, Err(TerrainError::Io(_))));
+
+        let empty_heightmap = Heightmap::new(0, 0, Vec::new());
+        assert!(matches!(validate_rtin_heightmap(&empty_heightmap), 
+            Err(TerrainError::EmptyImage)));
+    }
+
+    #[test]
+    fn test_build_terrain_from_height_source() {
+        let height_fn = |x: u32, y: u32| ((x as f32 * 0.5).sin() + y as f32 * 0.1).abs();
+        let procedural = FnHeightSource::new(9, 9, height_fn);
+        let grid = Heightmap::from_height_source(&procedural);
+
+        let procedural_mesh = rtin_build_terrain_from_heightmap(&procedural, 0.05);
+        let grid_mesh = rtin_build_terrain_from_heightmap(&grid, 0.05);
+
+        assert_eq!(procedural_mesh.vertices, grid_mesh.vertices);
+        assert_eq!(procedural_mesh.indices, grid_mesh.indices);
+    }
+
+    #[test]
+    fn test_rtin_mesh_skirt() {
+        let heightmap = sine_height_source(17, 17);
+        let terrain_mesh_data = rtin_build_terrain_from_heightmap(&heightmap, 0.2);
+        let max_error = terrain_mesh_data.errors.iter().cloned().fold(0f32, f32::max);
+        let border_vertices = terrain_mesh_data.vertices.iter()
+            .filter(|vertex| vertex.x == 0.0 || vertex.x == 16.0 || vertex.z == 0.0 || vertex.z == 16.0)
+            .count();
+
+        let load_options = TerrainImageLoadOptions {
+            max_image_height: 10.0,
+            pixel_side_length: 1.0,
+            skirt: Some(SkirtOptions { error_factor: 2.0, min_depth: 0.0 }),
+            ..Default::default()
+        };
+        let mesh = rtin_make_terrain_mesh(&terrain_mesh_data, &load_options, false);
+
+        let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+            Some(VertexAttributeValues::Float3(positions)) => positions.clone(),
+            _ => panic!("missing mesh positions"),
+        };
+        let vertices_len = terrain_mesh_data.vertices.len();
+        assert_eq!(positions.len(), vertices_len + border_vertices);
+
+        let skirt = mesh_skirt(&terrain_mesh_data.vertices);
+        for (skirt_vertex, top_vertex) in skirt.top_vertices.iter().enumerate() {
+            let top = positions[*top_vertex as usize];
+            let bottom = positions[vertices_len + skirt_vertex];
+            assert_eq!((bottom[0], bottom[2]), (top[0], top[2]));
+            assert!((top[1] - bottom[1] - max_error * 2.0 * 10.0).abs() < 1e-4);
+        }
+
+        match mesh.indices() {
+            Some(Indices::U32(indices)) => assert_eq!(
+                indices.len(), terrain_mesh_data.indices.len() + border_vertices * 6),
+            _ => panic!("missing mesh indices"),
+        }
+    }
+
+    #[test]
+    fn test_morph_heights() {
+        let heightmap = sine_height_source(17, 17);
+        let grid = rtin_grid_for_heightmap(&heightmap);
+        let errors_vec = build_triangle_errors_vec(&heightmap);
+        let triangles = rtin_select_triangles_for_heightmap(&heightmap, &grid, &errors_vec, 0.05);
+        let terrain_mesh_data = rtin_build_terrain_from_heightmap(&heightmap, 0.05);
+        assert_eq!(terrain_mesh_data.morph_heights.len(), terrain_mesh_data.vertices.len());
+
+        // the coarser selection merges every diamond whose children are all selected
+        let extent = rtin_grid_extent(&heightmap);
+        let mut coarser_triangles: Vec::<BinId> = triangles.iter()
+            .map(|bin_id| match rtin_diamond_morph_height(&heightmap, &grid, extent, *bin_id, 
+                    |bin_id| triangles.contains(&bin_id)) {
+                Some(_) => get_triangle_parent_bin_id(*bin_id).unwrap(),
+                None => *bin_id,
+            })
+            .collect();
+        coarser_triangles.sort();
+        coarser_triangles.dedup();
+        assert!(coarser_triangles.len() < triangles.len());
+
+        let coarser_height_at = |x: f32, z: f32| coarser_triangles.iter().find_map(|bin_id| {
+            let (a, b, c) = grid.triangle_coords(*bin_id);
+            let corner = |v: Vec2u32| (v[0] as f32, v[1] as f32,
+                sample_heightmap_height_corner_mean(&heightmap, v));
+            let (a, b, c) = (corner(a), corner(b), corner(c));
+            let area = (b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1);
+            let wb = ((x - a.0) * (c.1 - a.1) - (c.0 - a.0) * (z - a.1)) / area;
+            let wc = ((b.0 - a.0) * (z - a.1) - (x - a.0) * (b.1 - a.1)) / area;
+            let wa = 1.0 - wb - wc;
+            if wa >= 0.0 && wb >= 0.0 && wc >= 0.0 {
+                Some(wa * a.2 + wb * b.2 + wc * c.2)
+            } else {
+                None
+            }
+        }).unwrap();
+
+        let mut morphing_vertices = 0;
+        for (vertex, morph_height) in terrain_mesh_data.vertices.iter()
+                .zip(&terrain_mesh_data.morph_heights) {
+            assert!((*morph_height - coarser_height_at(vertex.x, vertex.z)).abs() < 1e-5);
+            if *morph_height != vertex.y {
+                morphing_vertices += 1;
+            }
+        }
+        assert!(morphing_vertices > 0);
+
+        let load_options = TerrainImageLoadOptions {
+            max_image_height: 10.0,
+            pixel_side_length: 1.0,
+            ..Default::default()
+        };
+        let mesh = rtin_make_terrain_mesh(&terrain_mesh_data, &load_options, false);
+        match mesh.attribute(TerrainMaterial::ATTRIBUTE_MORPH_HEIGHT) {
+            Some(VertexAttributeValues::Float(morph_heights)) => assert!(morph_heights.iter()
+                .zip(&terrain_mesh_data.morph_heights)
+                .all(|(mesh_height, morph_height)| *mesh_height == morph_height * 10.0)),
+            _ => panic!("missing morph heights"),
+        }
+    }
+
+    #[test]
+    fn test_build_terrain_for_view() {
+        let heightmap = sine_height_source(33, 33);
+        let load_options = TerrainImageLoadOptions {
+            max_image_height: 10.0,
+            pixel_side_length: 1.0,
+            ..Default::default()
+        };
+        let view_from = |camera_position| RtinView {
+            camera_position,
+            fov: 1.0,
+            viewport_height: 720.0,
+            max_pixel_error: 2.0,
+        };
+
+        let near = rtin_build_terrain_from_heightmap_for_view(
+            &heightmap, &view_from(Vec3::new(16.0, 12.0, 16.0)), &load_options);
+        let far = rtin_build_terrain_from_heightmap_for_view(
+            &heightmap, &view_from(Vec3::new(16.0, 12.0, 500.0)), &load_options);
+        assert!(near.indices.len() > far.indices.len());
+
+        // the triangles next to the camera are denser than the opposite side
+        let corner = view_from(Vec3::new(0.0, 12.0, 0.0));
+        let corner_mesh = rtin_build_terrain_from_heightmap_for_view(
+            &heightmap, &corner, &load_options);
+        let count_vertices_near = |x: f32, z: f32| corner_mesh.vertices.iter()
+            .filter(|vertex| (vertex.x - x).abs() <= 8.0 && (vertex.z - z).abs() <= 8.0)
+            .count();
+        assert!(count_vertices_near(0.0, 0.0) > count_vertices_near(32.0, 32.0));
+    }
+
+    #[test]
+    fn test_select_triangles_for_budget() {
+        let heightmap = sine_height_source(30, 20);
+        let grid = rtin_grid_for_heightmap(&heightmap);
+        let errors_vec = build_triangle_errors_vec(&heightmap);
+        // the triangles crossing the border are split whatever the budget
+        let coarsest_triangles = rtin_select_triangles_for_heightmap(
+            &heightmap, &grid, &errors_vec, f32::MAX).len();
+
+        let mut previous_max_error = f32::INFINITY;
+        for max_triangles in &[50, 200, 400, 1000, 100000] {
+            let selection = rtin_select_triangles_for_budget(
+                &heightmap, &grid, &errors_vec, RtinBudget::Triangles(*max_triangles));
+
+            assert!(selection.triangles.len() <= (*max_triangles).max(coarsest_triangles));
+            assert!(selection.max_error <= previous_max_error);
+            assert_eq!(selection.triangles, rtin_select_triangles_for_heightmap(
+                &heightmap, &grid, &errors_vec, selection.error_threshold));
+            previous_max_error = selection.max_error;
+        }
+        assert_eq!(previous_max_error, 0.0);
+
+        let terrain_mesh_data = rtin_build_terrain_from_heightmap_for_budget(
+            &heightmap, RtinBudget::Vertices(150));
+        assert!(terrain_mesh_data.vertices.len() <= 150);
+        assert!(terrain_mesh_data.vertices.len() > 100);
+    }
+
+    #[test]
+    fn test_deepest_level_leaves() {
+        // levels 29 and 30 of the largest grid, the children of level 30 would overflow
+        let grid_size = RTIN_MAX_GRID_SIDE + 1;
+        let deepest_parent: BinId = 1 << 30;
+        assert!(crate::rtin::triangle_has_children(deepest_parent, grid_size));
+        assert_eq!(get_triangle_children_bin_ids(deepest_parent), (1 << 31, 3 << 30));
+        for leaf in &[1u32 << 31, 3 << 30, BinId::MAX] {
+            assert!(!crate::rtin::triangle_has_children(*leaf, grid_size));
+        }
+
+        // selecting every triangle stops at the leaves
+        let heightmap = FnHeightSource::new(5, 5, |x, y| (x * y) as f32 * 0.01);
+        let grid = rtin_grid_for_heightmap(&heightmap);
+        let errors_vec = build_triangle_errors_vec(&heightmap);
+        let triangles = rtin_select_triangles_for_heightmap(&heightmap, &grid, &errors_vec, -1.0);
+        assert_eq!(triangles.len(), 4 * 4 * 2);
+        assert!(triangles.iter().all(|bin_id| !grid.has_children(*bin_id)));
+    }
+
+    #[test]
+    fn test_max_grid_side_bin_ids_fit_u32() {
+        let side = RTIN_MAX_GRID_SIDE;
+        let leaf_level = side.trailing_zeros() * 2;
+        assert_eq!(leaf_level, 30);
+
+        let leaf_level_start: BinId = get_index_level_start(leaf_level);
+        assert_eq!(leaf_level_start, (1 << 31) - 2);
+        assert_eq!(bin_id_to_level(index_to_bin_id(leaf_level_start)), leaf_level);
+
+        let last_index = (side as u64 * side as u64 * 4 - 3) as BinId;
+        assert_eq!(index_to_bin_id(last_index), BinId::MAX);
+        assert_eq!(bin_id_to_level(index_to_bin_id(last_index)), leaf_level);
+    }
+
+    #[test]
+    fn test_split_candidates_order_nan() {
+        let candidate = |error, bin_id| RtinSplitCandidate { error, bin_id };
+        let mut candidates: std::collections::BinaryHeap<RtinSplitCandidate> = vec![
+            candidate(0.5, 2), candidate(f32::NAN, 3), candidate(f32::INFINITY, 4), 
+            candidate(f32::NAN, 5), candidate(0.1, 6),
+        ].into_iter().collect();
+
+        let mut popped = Vec::new();
+        while let Some(candidate) = candidates.pop() {
+            popped.push(candidate.bin_id);
+        }
+        assert_eq!(popped, vec![5, 3, 4, 2, 6]);
+        assert_eq!(candidate(f32::NAN, 3), candidate(f32::NAN, 3));
+
+        // no-data heights are split like the border triangles
+        let heightmap = FnHeightSource::new(9, 9, |x, y| if (x, y) == (3, 5) { f32::NAN } else { 0.0 });
+        let grid = rtin_grid_for_heightmap(&heightmap);
+        let errors_vec = build_triangle_errors_vec(&heightmap);
+        let selection = rtin_select_triangles_for_budget(
+            &heightmap, &grid, &errors_vec, RtinBudget::Triangles(1000));
+        assert_eq!(selection.triangles, rtin_select_triangles_for_heightmap(
+            &heightmap, &grid, &errors_vec, selection.error_threshold));
+    }
+
+    #[test]
+    fn test_build_terrain_uvs() {
+        let heightmap = FnHeightSource::new(5, 3, |x, _| x as f32 * 0.25);
+        let terrain_mesh_data = rtin_build_terrain_from_heightmap(&heightmap, 0.0);
+
+        assert_eq!(terrain_mesh_data.uvs.len(), terrain_mesh_data.vertices.len());
+        for (vertex, uv) in terrain_mesh_data.vertices.iter().zip(&terrain_mesh_data.uvs) {
+            assert_eq!(uv.x, (vertex.x / 4.0).min(1.0));
+            assert_eq!(uv.y, (vertex.z / 2.0).min(1.0));
+        }
+        assert!(terrain_mesh_data.uvs.contains(&Vec2::new(1.0, 1.0)));
+    }
+
+    #[test]
+    fn test_rtin_terrain_reuses_errors() {
+        let procedural = FnHeightSource::new(17, 9, 
+            |x, y| ((x as f32 * 0.3).cos() * (y as f32 * 0.5).sin()).abs());
+        let heightmap = Heightmap::from_height_source(&procedural);
+        let rtin_terrain = RtinTerrain::new(heightmap.clone()).unwrap();
+
+        assert!(std::ptr::eq(rtin_terrain.heightmap().heights(), heightmap.heights()));
+        assert_eq!(rtin_terrain.errors(), &build_triangle_errors_vec(&heightmap)[..]);
+
+        for error_threshold in &[0.0f32, 0.05, 0.2, 1.0] {
+            let cached = rtin_terrain.mesh_data_for_threshold(*error_threshold);
+            let rebuilt = rtin_build_terrain_from_heightmap(&heightmap, *error_threshold);
+            assert_eq!(cached.vertices, rebuilt.vertices);
+            assert_eq!(cached.indices, rebuilt.indices);
+        }
+
+        assert!(matches!(RtinTerrain::new(Heightmap::new(0, 0, Vec::new())),
+            Err(TerrainError::EmptyImage)));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn test_parallel_matches_sequential() {
+        let heightmap = FnHeightSource::new(150, 129, 
+            |x, y| ((x as f32 * 0.07).sin() * (y as f32 * 0.05).cos()).abs());
+        let grid = rtin_grid_for_heightmap(&heightmap);
+
+        let errors_vec = build_triangle_errors_vec_with_grid(&heightmap, &grid);
+        let parallel_errors_vec = build_triangle_errors_vec_par(&heightmap, &grid);
+        assert!(errors_vec.iter().zip(&parallel_errors_vec)
+            .all(|(error, parallel_error)| error.to_bits() == parallel_error.to_bits()));
+
+        for error_threshold in &[0.0f32, 0.01, 0.1] {
+            assert_eq!(
+                rtin_select_triangles_for_heightmap_par(
+                    &heightmap, &grid, &errors_vec, *error_threshold),
+                rtin_select_triangles_for_heightmap(
+                    &heightmap, &grid, &errors_vec, *error_threshold));
+        }
+
+        let load_options = TerrainImageLoadOptions {
+            max_image_height: 50.0,
+            pixel_side_length: 1.0,
+            ..Default::default()
+        };
+        let view = RtinView {
+            camera_position: Vec3::new(10.0, 60.0, 20.0),
+            fov: 1.0,
+            viewport_height: 720.0,
+            max_pixel_error: 1.0,
+        };
+        assert_eq!(
+            rtin_select_triangles_for_view_par(
+                &heightmap, &grid, &errors_vec, &view, &load_options),
+            rtin_select_triangles_for_view(
+                &heightmap, &grid, &errors_vec, &view, &load_options));
+    }
+
+}
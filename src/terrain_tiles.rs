@@ -0,0 +1,328 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use crate::{
+    heightmap::Heightmap,
+    terrain_common::{Terrain, TerrainError, TerrainMeshes},
+    terrain_rtin::{RtinTerrain, RtinTerrainCache, propagate_triangle_errors_vec, rtin_store_terrain_meshes},
+};
+
+/// Marks a terrain as one tile of a grid of terrains meshed together,
+/// so that no crack opens along the borders between tiles.
+///
+/// Tile heightmaps must all be square, with the same side of 2^n + 1
+/// pixels, and neighbours share their border row or column: tile (x, z)
+/// covers the pixels from x * 2^n, z * 2^n of the whole area. Tiles are
+/// always meshed with their error threshold, thresholds may differ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TerrainTile {
+    /// towards the last heightmap column
+    pub x: i32,
+    /// towards the last heightmap row
+    pub z: i32,
+}
+
+impl TerrainTile {
+    pub fn new(x: i32, z: i32) -> Self {
+        TerrainTile { x, z }
+    }
+
+    /// where the tile goes for its borders to meet its neighbours
+    pub fn translation(&self, heightmap_side: u32, pixel_side_length: f32) -> Vec3 {
+        let tile_length = heightmap_side.saturating_sub(1) as f32 * pixel_side_length;
+        Vec3::new(self.x as f32 * tile_length, 0f32, self.z as f32 * tile_length)
+    }
+
+    pub fn neighbour(&self, border: TileBorder) -> TerrainTile {
+        match border {
+            TileBorder::North => TerrainTile::new(self.x, self.z - 1),
+            TileBorder::East => TerrainTile::new(self.x + 1, self.z),
+            TileBorder::South => TerrainTile::new(self.x, self.z + 1),
+            TileBorder::West => TerrainTile::new(self.x - 1, self.z),
+        }
+    }
+}
+
+/// side of a tile, north is the first heightmap row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TileBorder {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl TileBorder {
+    pub const ALL: [TileBorder; 4] =
+        [TileBorder::North, TileBorder::East, TileBorder::South, TileBorder::West];
+
+    pub fn opposite(self) -> TileBorder {
+        match self {
+            TileBorder::North => TileBorder::South,
+            TileBorder::East => TileBorder::West,
+            TileBorder::South => TileBorder::North,
+            TileBorder::West => TileBorder::East,
+        }
+    }
+
+    /// the i-th grid point of the border, from the west or north end
+    fn grid_point(self, i: u32, side: u32) -> (u32, u32) {
+        match self {
+            TileBorder::North => (i, 0),
+            TileBorder::East => (side, i),
+            TileBorder::South => (i, side),
+            TileBorder::West => (0, i),
+        }
+    }
+}
+
+/// error over threshold, the triangles to split are above 1
+fn split_error(error: f32, error_threshold: f32) -> f32 {
+    if error_threshold > 0f32 {
+        error / error_threshold
+    } else if error > 0f32 {
+        f32::INFINITY
+    } else {
+        0f32
+    }
+}
+
+/// Split errors of a grid of tiles, one vector per tile in the same order,
+/// to mesh with `RtinTerrain::mesh_for_split_errors`.
+///
+/// The heightmap errors of each tile over its threshold are raised along
+/// the shared borders, until both sides of every border split the same
+/// triangles and so have the same vertices
+pub fn rtin_stitch_tiles(
+    tiles: &[(TerrainTile, &RtinTerrain, f32)]) -> Result<Vec<Vec::<f32>>, TerrainError> {
+
+    let grid_size = match tiles.first() {
+        Some((_, rtin_terrain, _)) => rtin_terrain.grid().grid_size(),
+        None => return Ok(Vec::new()),
+    };
+
+    for (_, rtin_terrain, _) in tiles {
+        let heightmap = rtin_terrain.heightmap();
+        if heightmap.width() != grid_size || heightmap.height() != grid_size {
+            return Err(TerrainError::InvalidDimensions {
+                width: heightmap.width(),
+                height: heightmap.height()
+            });
+        }
+    }
+
+    let tile_indices: HashMap<TerrainTile, usize> = tiles.iter()
+        .enumerate()
+        .map(|(index, (tile, _, _))| (*tile, index))
+        .collect();
+
+    let mut split_errors: Vec<Vec::<f32>> = tiles.iter()
+        .map(|(_, rtin_terrain, error_threshold)| rtin_terrain.errors().iter()
+            .map(|error| split_error(*error, *error_threshold))
+            .collect())
+        .collect();
+
+    let side = grid_size - 1;
+    let errors_vec_index = |(x, y): (u32, u32)| (y * grid_size + x) as usize;
+
+    // errors only grow, taken from a finite set, so this ends
+    loop {
+        let mut raised = vec![false; tiles.len()];
+
+        for (index, (tile, _, _)) in tiles.iter().enumerate() {
+            for border in &TileBorder::ALL {
+                let neighbour_index = match tile_indices.get(&tile.neighbour(*border)) {
+                    Some(neighbour_index) => *neighbour_index,
+                    None => continue,
+                };
+
+                for i in 0..=side {
+                    let point = errors_vec_index(border.grid_point(i, side));
+                    let neighbour_point = errors_vec_index(border.opposite().grid_point(i, side));
+
+                    let neighbour_error = split_errors[neighbour_index][neighbour_point];
+                    if neighbour_error > split_errors[index][point] {
+                        split_errors[index][point] = neighbour_error;
+                        raised[index] = true;
+                    }
+                }
+            }
+        }
+
+        if !raised.contains(&true) {
+            break;
+        }
+
+        for (index, (_, rtin_terrain, _)) in tiles.iter().enumerate() {
+            if raised[index] {
+                propagate_triangle_errors_vec(rtin_terrain.grid(), &mut split_errors[index]);
+            }
+        }
+    }
+
+    Ok(split_errors)
+}
+
+/// re-mesh all the tiles when a tile terrain changes or one of their
+/// heightmaps loads or changes, once all the tile heightmaps are loaded
+pub fn rtin_terrain_tiles_system(
+    commands: &mut Commands,
+    mut tiles_outdated: Local<bool>,
+    mut heightmap_event_reader: Local<EventReader<AssetEvent<Heightmap>>>,
+    heightmap_events: Res<Events<AssetEvent<Heightmap>>>,
+    heightmaps: Res<Assets<Heightmap>>,
+    mut rtin_terrains: ResMut<RtinTerrainCache>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    changed_tiles_query: Query<&TerrainTile, Or<(Changed<Terrain>, Changed<TerrainTile>)>>,
+    mut tiles_query: Query<
+        (Entity, &Terrain, &TerrainTile, Option<&TerrainMeshes>, &mut Handle<Mesh>)>,
+) {
+    for event in heightmap_event_reader.iter(&heightmap_events) {
+        let handle = match event {
+            AssetEvent::Created { handle } |
+            AssetEvent::Modified { handle } |
+            AssetEvent::Removed { handle } => handle,
+        };
+
+        if tiles_query.iter_mut().any(|(_, terrain, ..)| terrain.heightmap == *handle) {
+            // the event may not have reached the cache yet
+            rtin_terrains.invalidate(handle);
+            *tiles_outdated = true;
+        }
+    }
+
+    if changed_tiles_query.iter().next().is_some() {
+        *tiles_outdated = true;
+    }
+
+    if !*tiles_outdated {
+        return;
+    }
+
+    let mut tiles = Vec::new();
+    for (entity, terrain, tile, terrain_meshes, mesh) in tiles_query.iter_mut() {
+        // not loaded yet, meshed once all are
+        let heightmap = match heightmaps.get(&terrain.heightmap) {
+            Some(heightmap) => heightmap,
+            None => return,
+        };
+
+        if let Err(err) = rtin_terrains.get_or_insert(&terrain.heightmap, heightmap) {
+            error!("cannot mesh terrain tile ({}, {}): {}", tile.x, tile.z, err);
+            *tiles_outdated = false;
+            return;
+        }
+
+        tiles.push((entity, terrain, *tile, terrain_meshes, mesh));
+    }
+
+    *tiles_outdated = false;
+
+    let rtin_tiles: Vec<(TerrainTile, &RtinTerrain, f32)> = tiles.iter()
+        .map(|(_, terrain, tile, _, _)| (
+            *tile,
+            rtin_terrains.get(&terrain.heightmap).unwrap(),
+            terrain.error_threshold))
+        .collect();
+
+    let split_errors = match rtin_stitch_tiles(&rtin_tiles) {
+        Ok(split_errors) => split_errors,
+        Err(err) => {
+            error!("cannot stitch terrain tiles: {}", err);
+            return;
+        }
+    };
+
+    let tiles = tiles.into_iter().zip(&rtin_tiles).zip(&split_errors);
+    for (((entity, terrain, _, terrain_meshes, mut mesh), (_, rtin_terrain, _)), split_errors) in tiles {
+        let new_meshes = rtin_terrain.mesh_for_split_errors(split_errors, &terrain.load_options);
+        let new_terrain_meshes = rtin_store_terrain_meshes(new_meshes, &mut meshes, terrain_meshes);
+
+        if terrain_meshes.is_none() {
+            *mesh = new_terrain_meshes.shaded.clone();
+            commands.insert_one(entity, new_terrain_meshes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::heightmap::FnHeightSource;
+
+    const SIDE: u32 = 32;
+
+    fn area_height(x: u32, y: u32) -> f32 {
+        let (x, y) = (x as f32, y as f32);
+        ((x * 0.21).sin() * (y * 0.13).cos() + (x * y * 0.002).sin()).abs() * 0.5
+    }
+
+    fn rtin_tile(tile: TerrainTile) -> RtinTerrain {
+        let source = FnHeightSource::new(SIDE + 1, SIDE + 1, |x, y| area_height(
+            (tile.x as u32) * SIDE + x, (tile.z as u32) * SIDE + y));
+        RtinTerrain::new(Heightmap::from_height_source(&source)).unwrap()
+    }
+
+    /// sorted positions along the border of the mesh vertices on it
+    fn border_vertices(rtin_terrain: &RtinTerrain, split_errors: &Vec::<f32>, 
+            border: TileBorder) -> Vec<u32> {
+        let mut positions: Vec<u32> = rtin_terrain.mesh_data_for_split_errors(split_errors)
+            .vertices.iter()
+            .map(|vertex| (vertex.x as u32, vertex.z as u32))
+            .filter_map(|(x, y)| (0..=SIDE)
+                .find(|i| border.grid_point(*i, SIDE) == (x, y)))
+            .collect();
+        positions.sort();
+        positions
+    }
+
+    #[test]
+    fn test_stitched_tiles_share_border_vertices() {
+        let tiles: Vec<TerrainTile> = (0..2)
+            .flat_map(|z| (0..2).map(move |x| TerrainTile::new(x, z)))
+            .collect();
+        let rtin_terrains: Vec<RtinTerrain> = tiles.iter().map(|tile| rtin_tile(*tile)).collect();
+
+        for thresholds in &[[0.05f32, 0.05, 0.05, 0.05], [0.01, 0.2, 0.08, 0.0]] {
+            let rtin_tiles: Vec<(TerrainTile, &RtinTerrain, f32)> = tiles.iter()
+                .zip(&rtin_terrains)
+                .zip(thresholds)
+                .map(|((tile, rtin_terrain), threshold)| (*tile, rtin_terrain, *threshold))
+                .collect();
+            let split_errors = rtin_stitch_tiles(&rtin_tiles).unwrap();
+
+            for (index, tile) in tiles.iter().enumerate() {
+                for border in &[TileBorder::East, TileBorder::South] {
+                    let neighbour_index = match tiles.iter()
+                            .position(|other| *other == tile.neighbour(*border)) {
+                        Some(neighbour_index) => neighbour_index,
+                        None => continue,
+                    };
+
+                    assert_eq!(
+                        border_vertices(&rtin_terrains[index], &split_errors[index], *border),
+                        border_vertices(&rtin_terrains[neighbour_index], 
+                            &split_errors[neighbour_index], border.opposite()));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_single_tile_matches_threshold_mesh() {
+        let tile = TerrainTile::new(0, 0);
+        let rtin_terrain = rtin_tile(tile);
+
+        let split_errors = rtin_stitch_tiles(&[(tile, &rtin_terrain, 0.25)]).unwrap();
+        let stitched = rtin_terrain.mesh_data_for_split_errors(&split_errors[0]);
+        let unstitched = rtin_terrain.mesh_data_for_threshold(0.25);
+
+        assert_eq!(stitched.vertices, unstitched.vertices);
+        assert_eq!(stitched.indices, unstitched.indices);
+
+        let odd_heightmap = Heightmap::new(SIDE, SIDE + 1, vec![0.0; (SIDE * (SIDE + 1)) as usize]);
+        let odd_terrain = RtinTerrain::new(odd_heightmap).unwrap();
+        assert!(matches!(
+            rtin_stitch_tiles(&[(tile, &rtin_terrain, 0.1), (tile.neighbour(TileBorder::East), &odd_terrain, 0.1)]),
+            Err(TerrainError::InvalidDimensions { .. })));
+    }
+}
@@ -1,7 +1,37 @@
 use bevy::prelude::*;
 use bevy_fly_camera::FlyCamera;
-use bevy_terrain::{terrain_common::TerrainMeshResource, terrain_rtin::rtin_load_terrain};
-use bevy_terrain::{terrain_common::Terrain, terrain_rtin::RtinParams};
+use crate::terrain_common::{ScreenSpaceErrorOptions, Terrain, TerrainMeshes};
+
+/// Debug helpers for the terrain demo: keyboard controls for the 
+/// RTIN error threshold, V to toggle the camera dependent refinement,
+/// a shaded/wireframe toggle and a menu shown while TAB is held. 
+/// Requires `TerrainPlugin` and a `FlyCamera`, built with the `debug` feature
+pub struct TerrainDebugPlugin;
+
+impl Plugin for TerrainDebugPlugin {
+    fn build(&self, app: &mut AppBuilder) {
+        app.init_resource::<ButtonMaterials>()
+            .init_resource::<TerrainDebugSettings>()
+            .add_startup_system(setup_ui.system())
+            .add_system(button_system.system())
+            .add_system(update_terrain_system.system())
+            .add_system(show_ui_system.system());
+    }
+}
+
+/// error threshold edited with +/-, applied to every terrain with R
+pub struct TerrainDebugSettings {
+    pub error_threshold: f32,
+}
+
+impl Default for TerrainDebugSettings {
+    fn default() -> Self {
+        TerrainDebugSettings {
+            error_threshold: 0.2
+        }
+    }
+}
+
 pub struct ButtonMaterials {
     shaded: Handle<ColorMaterial>,
     wireframe: Handle<ColorMaterial>,
@@ -26,40 +56,40 @@ enum MeshStyle {
 }
 
 pub fn update_terrain_system(
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut rtin_params: ResMut<RtinParams>,
+    mut debug_settings: ResMut<TerrainDebugSettings>,
     keyboard_input: Res<Input<KeyCode>>,
-    mut terrain_query: Query<(Entity, &mut Handle<Mesh>, &Terrain)>,
+    mut terrain_query: Query<&mut Terrain>,
     mut text_query: Query<&mut Text, With<RtinParamsMenu>>,
-    mut terrain_mesh_res: ResMut<TerrainMeshResource>,
-    commands: &mut Commands,
 ) {
     let mut reload = false;
 
     if keyboard_input.just_pressed(KeyCode::Plus) {
-        rtin_params.error_threshold += 0.05;
+        debug_settings.error_threshold += 0.05;
     } else if keyboard_input.just_released(KeyCode::Minus) {
-        rtin_params.error_threshold -= 0.05;
+        debug_settings.error_threshold -= 0.05;
     } else if keyboard_input.just_released(KeyCode::R) {
         reload = true;
+    } else if keyboard_input.just_released(KeyCode::V) {
+        for mut terrain in terrain_query.iter_mut() {
+            terrain.screen_space_error = match terrain.screen_space_error {
+                Some(_) => None,
+                None => Some(ScreenSpaceErrorOptions::default()),
+            };
+        }
     }
 
-    rtin_params.error_threshold = rtin_params.
+    debug_settings.error_threshold = debug_settings.
         error_threshold.max(0f32).min(1f32);
 
     for mut text in text_query.iter_mut() {
-        text.value = format!("{:.2}", rtin_params.error_threshold);
+        text.value = format!("{:.2}", debug_settings.error_threshold);
     }
 
     if reload {
-        let (terrain_shaded_mesh, terrain_wireframe_mesh) =
-            rtin_load_terrain("terrain.png", &rtin_params);
-
-        let terrain_shaded_mesh_handle = meshes.add(terrain_shaded_mesh);
-        let terrain_wireframe_mesh_handle = meshes.add(terrain_wireframe_mesh);
-
-        terrain_mesh_res.shaded = terrain_shaded_mesh_handle;
-        terrain_mesh_res.wireframe = terrain_wireframe_mesh_handle;
+        // changing the terrain re-meshes it
+        for mut terrain in terrain_query.iter_mut() {
+            terrain.error_threshold = debug_settings.error_threshold;
+        }
     }
 }
 
@@ -69,9 +99,8 @@ pub fn button_system(
         (&Interaction, &mut Handle<ColorMaterial>, &Children),
         (Mutated<Interaction>, With<Button>),
     >,
-    mut terrain_query: Query<(Entity, &mut Handle<Mesh>, &Terrain)>,
+    terrain_query: Query<(Entity, &TerrainMeshes), With<Terrain>>,
     mut text_query: Query<&mut Text>,
-    terrain_mesh_res: Res<TerrainMeshResource>,
     commands: &mut Commands,
 ) {
     let mut new_mesh_type = Option::<MeshStyle>::None;
@@ -110,13 +139,13 @@ pub fn button_system(
     if new_mesh_type.is_some() {
         let mesh_type = new_mesh_type.unwrap();
 
-        let new_mesh_handle = if mesh_type == MeshStyle::Shaded {
-            terrain_mesh_res.shaded.clone()
-        } else {
-            terrain_mesh_res.wireframe.clone()
-        };
+        for (entity, terrain_meshes) in terrain_query.iter() {
+            let new_mesh_handle = if mesh_type == MeshStyle::Shaded {
+                terrain_meshes.shaded.clone()
+            } else {
+                terrain_meshes.wireframe.clone()
+            };
 
-        for (entity, mut mesh, _terrain) in terrain_query.iter_mut() {
             commands.remove_one::<Handle<Mesh>>(entity);
             commands.set_current_entity(entity);
             commands.with(new_mesh_handle.clone());
@@ -132,7 +161,7 @@ pub fn setup_ui(
     asset_server: Res<AssetServer>,
     mut materials: ResMut<Assets<ColorMaterial>>,
     button_materials: Res<ButtonMaterials>,
-    rtin_params: ResMut<RtinParams>,
+    debug_settings: Res<TerrainDebugSettings>,
 ) {
     commands
         .spawn(CameraUiBundle::default())
@@ -153,7 +182,7 @@ pub fn setup_ui(
                     is_transparent: false,
                 },
                 text: Text {
-                    value: format!("{}", rtin_params.error_threshold).to_string(),
+                    value: format!("{}", debug_settings.error_threshold).to_string(),
                     font: asset_server.load("fonts/FiraSans-Bold.ttf"),
                     style: TextStyle {
                         font_size: 40.0,
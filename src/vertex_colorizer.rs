@@ -0,0 +1,229 @@
+use bevy::math::Vec3;
+use palette::Srgb;
+use crate::color_ramp::{ColorRamp, ColorRampHeight};
+
+/// what is known about a terrain vertex when picking its color
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainVertex {
+    /// world space position
+    pub position: Vec3,
+    /// world space unit normal
+    pub normal: Vec3,
+    /// heightmap value, from 0 to 1
+    pub height: f32,
+    /// laplacian of the world space height, positive in valleys
+    /// and negative on ridges
+    pub curvature: f32,
+    /// largest RTIN approximation error of the triangles around
+    /// the vertex, in heightmap units like the error threshold
+    pub error: f32,
+    /// deepest RTIN level of the triangles around the vertex
+    pub level: u32,
+}
+
+/// Computes the sRGB color of each terrain vertex.
+///
+/// A `ColorRamp` is itself a colorizer, coloring by height
+pub trait VertexColorizer: Send + Sync {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3];
+}
+
+fn ramp_color(ramp: &ColorRamp, value: f32) -> [f32; 3] {
+    let color = ramp.color_at(value);
+    [color.red, color.green, color.blue]
+}
+
+impl VertexColorizer for ColorRamp {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3] {
+        match self.height {
+            ColorRampHeight::Normalized => ramp_color(self, vertex.height),
+            ColorRampHeight::Absolute => ramp_color(self, vertex.position.y),
+        }
+    }
+}
+
+/// colors by slope angle, the ramp goes from flat at 0
+/// to vertical at 1
+pub struct SlopeColorizer {
+    pub ramp: ColorRamp,
+}
+
+impl Default for SlopeColorizer {
+    fn default() -> Self {
+        SlopeColorizer {
+            ramp: ColorRamp::viridis(),
+        }
+    }
+}
+
+impl VertexColorizer for SlopeColorizer {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3] {
+        let slope = vertex.normal.y.max(-1.0).min(1.0).acos();
+        ramp_color(&self.ramp, slope / std::f32::consts::FRAC_PI_2)
+    }
+}
+
+/// Colors by aspect, the compass direction the slope faces: the ramp
+/// turns from +Z at 0 through +X at 0.25 and back to +Z at 1.
+/// Flat areas have no aspect and take `flat_color`
+pub struct AspectColorizer {
+    pub ramp: ColorRamp,
+    pub flat_color: [f32; 3],
+    /// slope angle, in radians, below which a vertex is flat
+    pub flat_slope: f32,
+}
+
+impl Default for AspectColorizer {
+    fn default() -> Self {
+        AspectColorizer {
+            ramp: ColorRamp::new(vec![
+                (0.0 / 6.0, Srgb::new(1.0, 0.0, 0.0)),
+                (1.0 / 6.0, Srgb::new(1.0, 1.0, 0.0)),
+                (2.0 / 6.0, Srgb::new(0.0, 1.0, 0.0)),
+                (3.0 / 6.0, Srgb::new(0.0, 1.0, 1.0)),
+                (4.0 / 6.0, Srgb::new(0.0, 0.0, 1.0)),
+                (5.0 / 6.0, Srgb::new(1.0, 0.0, 1.0)),
+                (6.0 / 6.0, Srgb::new(1.0, 0.0, 0.0)),
+            ], ColorRampHeight::Normalized),
+            flat_color: [0.5, 0.5, 0.5],
+            flat_slope: 0.01,
+        }
+    }
+}
+
+impl VertexColorizer for AspectColorizer {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3] {
+        let normal = vertex.normal;
+        if normal.y.max(-1.0).min(1.0).acos() < self.flat_slope {
+            return self.flat_color;
+        }
+
+        let angle = normal.x.atan2(normal.z);
+        let turn = angle / std::f32::consts::TAU;
+        ramp_color(&self.ramp, turn.rem_euclid(1.0))
+    }
+}
+
+/// colors by curvature, mapping `-scale..scale` to the
+/// ramp from ridges at 0 to valleys at 1
+pub struct CurvatureColorizer {
+    pub ramp: ColorRamp,
+    pub scale: f32,
+}
+
+impl Default for CurvatureColorizer {
+    fn default() -> Self {
+        CurvatureColorizer {
+            ramp: ColorRamp::new(vec![
+                (0.0, Srgb::new(0.7, 0.1, 0.1)),
+                (0.5, Srgb::new(0.95, 0.95, 0.95)),
+                (1.0, Srgb::new(0.1, 0.2, 0.7)),
+            ], ColorRampHeight::Normalized),
+            scale: 0.1,
+        }
+    }
+}
+
+impl VertexColorizer for CurvatureColorizer {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3] {
+        ramp_color(&self.ramp, 0.5 + 0.5 * vertex.curvature / self.scale)
+    }
+}
+
+/// colors by RTIN approximation error, from exact at 0
+/// to `max_error` and above at 1
+pub struct ErrorColorizer {
+    pub ramp: ColorRamp,
+    pub max_error: f32,
+}
+
+impl Default for ErrorColorizer {
+    fn default() -> Self {
+        ErrorColorizer {
+            ramp: ColorRamp::viridis(),
+            max_error: 0.1,
+        }
+    }
+}
+
+impl VertexColorizer for ErrorColorizer {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3] {
+        ramp_color(&self.ramp, vertex.error / self.max_error)
+    }
+}
+
+/// colors by RTIN triangle level, from the two root
+/// triangles at 0 to `max_level` and deeper at 1
+pub struct LevelColorizer {
+    pub ramp: ColorRamp,
+    pub max_level: u32,
+}
+
+impl Default for LevelColorizer {
+    fn default() -> Self {
+        LevelColorizer {
+            ramp: ColorRamp::viridis(),
+            max_level: 20,
+        }
+    }
+}
+
+impl VertexColorizer for LevelColorizer {
+    fn color(&self, vertex: &TerrainVertex) -> [f32; 3] {
+        ramp_color(&self.ramp, vertex.level as f32 / self.max_level.max(1) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vertex_with_normal(normal: Vec3) -> TerrainVertex {
+        TerrainVertex {
+            position: Vec3::zero(),
+            normal: normal.normalize(),
+            height: 0.0,
+            curvature: 0.0,
+            error: 0.0,
+            level: 0,
+        }
+    }
+
+    fn assert_color_near(left: [f32; 3], right: [f32; 3]) {
+        for i in 0..3 {
+            assert!((left[i] - right[i]).abs() < 1e-4, "{:?} != {:?}", left, right);
+        }
+    }
+
+    #[test]
+    fn test_slope_and_aspect_colorizers() {
+        let black_to_white = ColorRamp::new(vec![
+            (0.0, Srgb::new(0.0, 0.0, 0.0)),
+            (1.0, Srgb::new(1.0, 1.0, 1.0)),
+        ], ColorRampHeight::Normalized);
+
+        let slope = SlopeColorizer { ramp: black_to_white.clone() };
+        assert_color_near(slope.color(&vertex_with_normal(Vec3::unit_y())), [0.0; 3]);
+        assert_color_near(slope.color(&vertex_with_normal(Vec3::unit_x())), [1.0; 3]);
+
+        let aspect = AspectColorizer::default();
+        assert_eq!(aspect.color(&vertex_with_normal(Vec3::unit_y())), aspect.flat_color);
+        let facing_x = aspect.color(&vertex_with_normal(Vec3::new(1.0, 1.0, 0.0)));
+        let facing_z = aspect.color(&vertex_with_normal(Vec3::new(0.0, 1.0, 1.0)));
+        assert_ne!(facing_x, facing_z);
+    }
+
+    #[test]
+    fn test_height_colorizer_uses_ramp_height() {
+        let mut vertex = vertex_with_normal(Vec3::unit_y());
+        vertex.height = 0.25;
+        vertex.position = Vec3::new(0.0, 5.0, 0.0);
+
+        let ramp = ColorRamp::terrain();
+        assert_eq!(ramp.color(&vertex), ramp_color(&ramp, 0.25));
+
+        let mut absolute = ramp.clone();
+        absolute.height = ColorRampHeight::Absolute;
+        assert_eq!(absolute.color(&vertex), ramp_color(&ramp, 5.0));
+    }
+}